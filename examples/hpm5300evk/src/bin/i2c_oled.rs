@@ -332,7 +332,7 @@ fn main() -> ! {
         use hal::i2c::*;
         i2c_config.mode = I2cMode::FastPlus;
     }
-    let i2c = hal::i2c::I2c::new_blocking(p.I2C2, p.PB08, p.PB09, i2c_config);
+    let i2c = hal::i2c::I2c::new_blocking(p.I2C2, p.PB08, p.PB09, i2c_config).unwrap();
 
     let mut screen = SSD1306::new(i2c, 0x3C);
 
@@ -207,7 +207,7 @@ async fn main(_spawner: embassy_executor::Spawner) -> ! {
         i2c_config.mode = I2cMode::Fast;
         i2c_config.timeout = Duration::from_secs(1);
     }
-    let i2c = I2c::new_blocking(r.i2c.i2c3, r.i2c.scl, r.i2c.sda, i2c_config);
+    let i2c = I2c::new_blocking(r.i2c.i2c3, r.i2c.scl, r.i2c.sda, i2c_config).unwrap();
 
     let mut ds3231m = DS3231M::new(i2c);
 
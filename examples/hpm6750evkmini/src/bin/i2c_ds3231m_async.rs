@@ -216,7 +216,7 @@ async fn main(_spawner: embassy_executor::Spawner) -> ! {
         i2c_config.mode = I2cMode::Fast;
         i2c_config.timeout = Duration::from_secs(1);
     }
-    let i2c = I2c::new(r.i2c.i2c3, r.i2c.scl, r.i2c.sda, Irqs, p.HDMA_CH2, i2c_config);
+    let i2c = I2c::new(r.i2c.i2c3, r.i2c.scl, r.i2c.sda, Irqs, p.HDMA_CH2, i2c_config).unwrap();
 
     println!("i2c init");
     let mut ds3231m = DS3231M::new(i2c);
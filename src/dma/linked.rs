@@ -0,0 +1,284 @@
+//! Linked list (scatter-gather) transfers
+//!
+//! A chain of blocks is run by the DMA controller without CPU reloads. Each block is stored in a
+//! [`DmaLinkedDescriptor`] of caller provided storage, linking to the next one. In [`LinkMode::Loop`],
+//! the last block links back to the first, e.g. for ping-pong buffers or continuous streaming.
+//!
+//! On parts with D-cache, the descriptors must be in non-cacheable memory, like DMA buffers.
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::sync::atomic::{fence, Ordering};
+use core::task::{Context, Poll};
+
+use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+use futures_util::future::poll_fn;
+
+use super::word::{Word, WordSize};
+use super::{AnyChannel, Channel, Dir, DmaLinkedDescriptor, HandshakeMode, Request, TransferOptions, STATE};
+use crate::pac::dma::vals::AddrCtrl;
+
+/// What follows the last block of a linked list transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkMode {
+    /// Stop after the last block.
+    Once,
+    /// Link the last block back to the first, until the transfer is aborted.
+    Loop,
+}
+
+/// One block of a linked list transfer.
+pub struct LinkedBlock<'a> {
+    dir: Dir,
+    src_addr: *const u32,
+    src_addr_ctrl: AddrCtrl,
+    dst_addr: *mut u32,
+    dst_addr_ctrl: AddrCtrl,
+    width: WordSize,
+    count: usize,
+    _phantom: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> LinkedBlock<'a> {
+    /// Peripheral to memory block.
+    pub fn read<W: Word>(peri_addr: *mut W, buf: &'a mut [W]) -> Self {
+        unsafe { Self::read_raw(peri_addr, buf) }
+    }
+
+    /// Peripheral to memory block, using raw pointers.
+    pub unsafe fn read_raw<W: Word>(peri_addr: *mut W, buf: *mut [W]) -> Self {
+        Self {
+            dir: Dir::PeripheralToMemory,
+            src_addr: peri_addr as *const u32,
+            src_addr_ctrl: AddrCtrl::FIXED,
+            dst_addr: buf as *mut W as *mut u32,
+            dst_addr_ctrl: AddrCtrl::INCREMENT,
+            width: W::size(),
+            count: buf.len(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Memory to peripheral block.
+    pub fn write<W: Word>(buf: &'a [W], peri_addr: *mut W) -> Self {
+        unsafe { Self::write_raw(buf, peri_addr) }
+    }
+
+    /// Memory to peripheral block, using raw pointers.
+    pub unsafe fn write_raw<W: Word>(buf: *const [W], peri_addr: *mut W) -> Self {
+        Self {
+            dir: Dir::MemoryToPeripheral,
+            src_addr: buf as *const W as *const u32,
+            src_addr_ctrl: AddrCtrl::INCREMENT,
+            dst_addr: peri_addr as *mut u32,
+            dst_addr_ctrl: AddrCtrl::FIXED,
+            width: W::size(),
+            count: buf.len(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Memory to memory block.
+    pub fn copy<W: Word>(src: &'a [W], dst: &'a mut [W]) -> Self {
+        assert_eq!(src.len(), dst.len());
+
+        Self {
+            dir: Dir::MemoryToMemory,
+            src_addr: src.as_ptr() as *const u32,
+            src_addr_ctrl: AddrCtrl::INCREMENT,
+            dst_addr: dst.as_mut_ptr() as *mut u32,
+            dst_addr_ctrl: AddrCtrl::INCREMENT,
+            width: W::size(),
+            count: src.len(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl AnyChannel {
+    /// Current configuration of the channel, as a descriptor.
+    fn to_descriptor(&self, linked_ptr: u32) -> DmaLinkedDescriptor {
+        let info = self.info();
+        let ch_cr = info.dma.regs().chctrl(info.num);
+
+        // descriptors are loaded into CTRL, enabled
+        let mut ctrl = ch_cr.ctrl().read();
+        ctrl.set_enable(true);
+
+        DmaLinkedDescriptor {
+            ctrl: ctrl.0,
+            trans_size: ch_cr.tran_size().read().0,
+            src_addr: ch_cr.src_addr().read(),
+            src_addr_high: 0,
+            dst_addr: ch_cr.dst_addr().read(),
+            dst_addr_high: 0,
+            linked_ptr,
+            linked_ptr_high: 0,
+        }
+    }
+}
+
+/// Linked list DMA transfer.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct LinkedTransfer<'a> {
+    channel: PeripheralRef<'a, AnyChannel>,
+    completed: usize,
+}
+
+impl<'a> LinkedTransfer<'a> {
+    /// Create and start a linked list transfer of `blocks`, using one of `descriptors` per block.
+    ///
+    /// All blocks must have the same direction, `request` is the DMAMUX request of peripheral blocks.
+    /// `options` apply to all blocks, `options.complete_transfer_irq` enables the interrupt at the end of
+    /// each block, see [`wait_for_block`](Self::wait_for_block).
+    pub unsafe fn new(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        request: Request,
+        blocks: &[LinkedBlock<'a>],
+        descriptors: &'a mut [DmaLinkedDescriptor],
+        mode: LinkMode,
+        mut options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+        let channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
+
+        assert!(!blocks.is_empty());
+        assert!(descriptors.len() >= blocks.len());
+
+        let dir = blocks[0].dir;
+        assert!(blocks.iter().all(|b| b.dir == dir));
+
+        let handshake = match dir {
+            Dir::MemoryToPeripheral => HandshakeMode::Destination,
+            Dir::PeripheralToMemory => HandshakeMode::Source,
+            Dir::MemoryToMemory => HandshakeMode::Normal,
+        };
+        options.circular = false;
+
+        let n = blocks.len();
+        let base = descriptors.as_ptr() as u32;
+        let linked_ptr = |i: usize| -> u32 {
+            let next = if i + 1 < n {
+                i + 1
+            } else if mode == LinkMode::Loop {
+                0
+            } else {
+                return 0;
+            };
+            sys_address(base + (next * core::mem::size_of::<DmaLinkedDescriptor>()) as u32)
+        };
+
+        // the channel registers encode each block, the first block is configured last
+        for (i, block) in blocks.iter().enumerate().rev() {
+            assert!(block.count > 0);
+
+            channel.configure(
+                request,
+                dir,
+                block.src_addr,
+                block.width,
+                block.src_addr_ctrl,
+                block.dst_addr,
+                block.width,
+                block.dst_addr_ctrl,
+                block.count,
+                handshake,
+                options,
+            );
+            descriptors[i] = channel.to_descriptor(linked_ptr(i));
+        }
+
+        let info = channel.info();
+        info.dma
+            .regs()
+            .chctrl(info.num)
+            .llpointer()
+            .modify(|w| w.0 = linked_ptr(0));
+
+        STATE[channel.id as usize].complete_count.store(0, Ordering::Release);
+
+        // descriptors are read by the DMA controller
+        fence(Ordering::SeqCst);
+
+        channel.start();
+
+        Self { channel, completed: 0 }
+    }
+
+    /// Request the transfer to stop.
+    ///
+    /// This doesn't immediately stop the transfer, you have to wait until [`is_running`](Self::is_running) returns false.
+    pub fn request_abort(&mut self) {
+        self.channel.abort()
+    }
+
+    /// Return whether this transfer is still running.
+    pub fn is_running(&mut self) -> bool {
+        let info = self.channel.info();
+        info.dma.regs().chctrl(info.num).ctrl().read().enable()
+    }
+
+    /// Number of blocks completed since the start, counted by the transfer complete interrupt.
+    ///
+    /// The last completed block is `(count - 1) % blocks.len()`.
+    pub fn completed_blocks(&self) -> usize {
+        STATE[self.channel.id as usize].complete_count.load(Ordering::Acquire)
+    }
+
+    /// Wait for the end of a block, returns [`completed_blocks`](Self::completed_blocks).
+    ///
+    /// If more than one block completed since the last call, the count increased by more than one.
+    pub async fn wait_for_block(&mut self) -> usize {
+        let id = self.channel.id as usize;
+        let completed = self.completed;
+
+        let count = poll_fn(|cx| {
+            STATE[id].waker.register(cx.waker());
+
+            let count = STATE[id].complete_count.load(Ordering::Acquire);
+            if count != completed {
+                Poll::Ready(count)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        self.completed = count;
+        count
+    }
+}
+
+impl<'a> Drop for LinkedTransfer<'a> {
+    fn drop(&mut self) {
+        self.request_abort();
+        while self.is_running() {}
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        fence(Ordering::SeqCst);
+    }
+}
+
+impl<'a> Unpin for LinkedTransfer<'a> {}
+impl<'a> Future for LinkedTransfer<'a> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        STATE[self.channel.id as usize].waker.register(cx.waker());
+
+        if self.is_running() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+/// Address of a descriptor, as seen by the DMA controller
+fn sys_address(addr: u32) -> u32 {
+    #[cfg(hpm67)]
+    return super::v1::core_local_mem_to_sys_address(0, addr);
+    #[cfg(not(hpm67))]
+    return addr;
+}
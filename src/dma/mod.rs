@@ -25,9 +25,18 @@ pub use v1::*;
 
 pub mod word;
 
+mod linked;
+pub use linked::*;
+
+#[cfg(ip_feature_dma_v2)]
+mod ringbuffer;
+#[cfg(ip_feature_dma_v2)]
+pub use ringbuffer::OverrunError;
+
 mod util;
 pub(crate) use util::*;
 
+use self::word::WordSize;
 use crate::pac;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -35,6 +44,7 @@ use crate::pac;
 enum Dir {
     MemoryToPeripheral,
     PeripheralToMemory,
+    MemoryToMemory,
 }
 
 pub(crate) struct ChannelInfo {
@@ -59,6 +69,41 @@ impl DmaInfo {
             DmaInfo::XDMA(dma) => dma,
         }
     }
+
+    /// TRANSFER_WIDTH_MAX
+    fn max_width(&self) -> WordSize {
+        match self {
+            DmaInfo::HDMA(_) => WordSize::FourBytes,
+            DmaInfo::XDMA(_) => WordSize::EightBytes,
+        }
+    }
+
+    /// PER_BURST_MAX
+    fn max_burst(&self) -> usize {
+        match self {
+            DmaInfo::HDMA(_) => 128,
+            DmaInfo::XDMA(_) => 1024,
+        }
+    }
+}
+
+/// Widest transfer width of a memory to memory transfer, allowed by the alignment of addresses and length.
+fn memory_width(max: WordSize, src_addr: u32, dst_addr: u32, len: usize) -> WordSize {
+    let align = src_addr | dst_addr | len as u32;
+
+    [WordSize::EightBytes, WordSize::FourBytes, WordSize::TwoBytes]
+        .into_iter()
+        .find(|width| width.bytes() <= max.bytes() && width.aligned(align))
+        .unwrap_or(WordSize::OneByte)
+}
+
+/// Largest burst of a memory to memory transfer, dividing the transfer count.
+fn memory_burst(count: usize, max: usize) -> usize {
+    let mut burst = max;
+    while count % burst != 0 {
+        burst >>= 1;
+    }
+    burst
 }
 
 /// DMA request type alias. (also known as DMA channel number)
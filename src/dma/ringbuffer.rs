@@ -0,0 +1,226 @@
+//! DMA ring buffer bookkeeping, shared by the circular-mode DMA drivers.
+//!
+//! The DMA position is derived from the remaining transfers of the current round, and the number of
+//! completed rounds is counted by the transfer complete interrupt.
+
+use core::ops::Range;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Waker;
+
+use super::word::Word;
+
+/// DMA overrun, the DMA writer has overwritten data that has not been read yet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OverrunError;
+
+/// Channel operations needed by the ring buffer.
+pub(crate) trait DmaCtrl {
+    /// Remaining transfers of the current round, i.e. TRANSIZE.
+    fn get_remaining_transfers(&self) -> usize;
+
+    /// Number of rounds the DMA has completed since the last reset.
+    fn get_complete_count(&self) -> usize;
+
+    /// Reset the completed round counter, returning the value before the reset.
+    fn reset_complete_count(&mut self) -> usize;
+
+    /// Register the waker to be woken on half transfer or transfer complete.
+    fn set_waker(&mut self, waker: &Waker);
+}
+
+/// Reading side of a ring buffer that is filled by a circular DMA transfer.
+///
+/// The DMA writes from the beginning of `dma_buf` to the end, then starts over.
+/// `start` is the index of the next word to be read by the user.
+pub(crate) struct ReadableDmaRingBuffer<'a, W: Word> {
+    pub(crate) dma_buf: &'a mut [W],
+    start: usize,
+}
+
+impl<'a, W: Word> ReadableDmaRingBuffer<'a, W> {
+    pub fn new(dma_buf: &'a mut [W]) -> Self {
+        Self { dma_buf, start: 0 }
+    }
+
+    /// Reset the ring buffer to its initial state.
+    pub fn clear(&mut self, dma: &mut impl DmaCtrl) {
+        self.start = 0;
+        dma.reset_complete_count();
+    }
+
+    /// Capacity of the ring buffer, in words.
+    pub const fn cap(&self) -> usize {
+        self.dma_buf.len()
+    }
+
+    /// The current position of the DMA writer.
+    fn pos(&self, dma: &impl DmaCtrl) -> usize {
+        self.cap() - dma.get_remaining_transfers()
+    }
+
+    /// Read words from the ring buffer.
+    ///
+    /// Returns a tuple of the number of words read and the number of words still available.
+    /// `OverrunError` is returned if the DMA writer has overwritten unread data.
+    pub fn read(&mut self, dma: &mut impl DmaCtrl, buf: &mut [W]) -> Result<(usize, usize), OverrunError> {
+        // The algorithm is optimistic: the data is copied first, then the DMA position and
+        // complete count are checked again to make sure the copied range was not overwritten
+        // while copying. The TC interrupt might be delayed, so the complete count alone can not
+        // be trusted at the beginning of the read.
+        let end = self.pos(dma);
+
+        if self.start == end && dma.get_complete_count() == 0 {
+            // nothing to read
+            Ok((0, self.cap()))
+        } else if self.start < end {
+            // the unread part does not wrap
+            let len = self.copy_to(buf, self.start..end);
+
+            compiler_fence(Ordering::SeqCst);
+
+            // check if DMA has wrapped, and whether it is inside the range we could have copied
+            let (pos, complete_count) = critical_section::with(|_| (self.pos(dma), dma.get_complete_count()));
+            if (pos >= self.start && pos < end) || (complete_count > 0 && pos >= end) || complete_count > 1 {
+                Err(OverrunError)
+            } else {
+                self.start = (self.start + len) % self.cap();
+
+                Ok((len, self.cap() - self.start))
+            }
+        } else if self.start + buf.len() < self.cap() {
+            // the unread part wraps, but `buf` can not hold the whole tail of the DMA buffer
+            let len = self.copy_to(buf, self.start..self.cap());
+
+            compiler_fence(Ordering::SeqCst);
+
+            let pos = self.pos(dma);
+            if pos > self.start || pos < end || dma.get_complete_count() > 1 {
+                Err(OverrunError)
+            } else {
+                self.start = (self.start + len) % self.cap();
+
+                Ok((len, self.start + end))
+            }
+        } else {
+            // the unread part wraps, and `buf` can hold the whole tail of the DMA buffer,
+            // the next read starts from the beginning of the DMA buffer
+            let tail = self.copy_to(buf, self.start..self.cap());
+            let head = self.copy_to(&mut buf[tail..], 0..end);
+
+            compiler_fence(Ordering::SeqCst);
+
+            let pos = self.pos(dma);
+            if pos > self.start || pos < end || dma.reset_complete_count() > 1 {
+                Err(OverrunError)
+            } else {
+                self.start = head;
+
+                Ok((tail + head, self.cap() - self.start))
+            }
+        }
+    }
+
+    /// Copy from the DMA buffer in `data_range` into `buf`, returns the number of words copied.
+    fn copy_to(&mut self, buf: &mut [W], data_range: Range<usize>) -> usize {
+        let length = usize::min(data_range.len(), buf.len());
+
+        // The DMA buffer is being written by the DMA controller at the same time,
+        // so use volatile reads instead of `copy_from_slice`.
+        unsafe {
+            let dma_buf = self.dma_buf.as_ptr();
+
+            for i in 0..length {
+                buf[i] = core::ptr::read_volatile(dma_buf.add(data_range.start + i));
+            }
+        }
+
+        length
+    }
+}
+
+/// Writing side of a ring buffer that is sent by a circular DMA transfer.
+///
+/// The DMA reads from the beginning of `dma_buf` to the end, then starts over.
+/// `written` is the number of words written by the user, counted from the beginning of the current DMA round.
+/// The whole buffer is sent in the first round, so it starts at the capacity.
+pub(crate) struct WritableDmaRingBuffer<'a, W: Word> {
+    pub(crate) dma_buf: &'a mut [W],
+    written: usize,
+}
+
+impl<'a, W: Word> WritableDmaRingBuffer<'a, W> {
+    pub fn new(dma_buf: &'a mut [W]) -> Self {
+        let written = dma_buf.len();
+        Self { dma_buf, written }
+    }
+
+    /// Reset the ring buffer to its initial state, the whole buffer is sent in the next round.
+    pub fn clear(&mut self, dma: &mut impl DmaCtrl) {
+        self.written = self.cap();
+        dma.reset_complete_count();
+    }
+
+    /// Capacity of the ring buffer, in words.
+    pub const fn cap(&self) -> usize {
+        self.dma_buf.len()
+    }
+
+    /// The current position of the DMA reader.
+    fn pos(&self, dma: &impl DmaCtrl) -> usize {
+        self.cap() - dma.get_remaining_transfers()
+    }
+
+    /// Move to the current DMA round, returns the DMA position in it.
+    ///
+    /// `OverrunError` is returned if the DMA reader has passed the written words.
+    fn sync(&mut self, dma: &mut impl DmaCtrl) -> Result<usize, OverrunError> {
+        // The position is read after the complete count. A wrap in between, or a delayed TC interrupt,
+        // makes the DMA look one round behind, which only underestimates the free space.
+        let rounds = dma.reset_complete_count();
+        let pos = self.pos(dma);
+
+        self.written = self.written.checked_sub(rounds * self.cap()).ok_or(OverrunError)?;
+
+        if pos > self.written {
+            Err(OverrunError)
+        } else {
+            Ok(pos)
+        }
+    }
+
+    /// Write words to the ring buffer.
+    ///
+    /// Returns a tuple of the number of words written and the free space left, in words.
+    /// `OverrunError` is returned if the DMA reader has sent words that were not written yet.
+    pub fn write(&mut self, dma: &mut impl DmaCtrl, buf: &[W]) -> Result<(usize, usize), OverrunError> {
+        let pos = self.sync(dma)?;
+        let free = self.cap().saturating_sub(self.written - pos);
+
+        let len = usize::min(free, buf.len());
+        self.copy_from(&buf[..len], self.written);
+
+        compiler_fence(Ordering::SeqCst);
+
+        // check the DMA has not passed the beginning of the copied range while copying
+        self.sync(dma)?;
+        self.written += len;
+
+        Ok((len, free - len))
+    }
+
+    /// Copy `buf` into the DMA buffer, starting at `start` and wrapping around.
+    fn copy_from(&mut self, buf: &[W], start: usize) {
+        let cap = self.cap();
+
+        // The DMA buffer is being read by the DMA controller at the same time,
+        // so use volatile writes instead of `copy_from_slice`.
+        unsafe {
+            let dma_buf = self.dma_buf.as_mut_ptr();
+
+            for (i, &w) in buf.iter().enumerate() {
+                core::ptr::write_volatile(dma_buf.add((start + i) % cap), w);
+            }
+        }
+    }
+}
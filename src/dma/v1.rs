@@ -95,8 +95,8 @@ impl Burst {
 }
 
 pub(crate) struct ChannelState {
-    waker: AtomicWaker,
-    complete_count: AtomicUsize,
+    pub(super) waker: AtomicWaker,
+    pub(super) complete_count: AtomicUsize,
 }
 
 impl ChannelState {
@@ -167,6 +167,12 @@ unsafe fn dma_on_irq(r: pac::dma::Dma, mux_num_base: u32) {
         r.int_status().write(|w| w.0 = abort << 8); // W1C
     }
 
+    // complete count is used by linked list transfers, to count completed blocks
+    for i in BitIter(tc) {
+        let id = (i + mux_num_base) as usize;
+        STATE[id].complete_count.fetch_add(1, Ordering::Release);
+    }
+
     for i in BitIter(tc | abort) {
         let id = (i + mux_num_base) as usize;
         STATE[id].waker.wake();
@@ -204,7 +210,7 @@ impl HandshakeMode {
 }
 
 impl AnyChannel {
-    unsafe fn configure(
+    pub(super) unsafe fn configure(
         &self,
         request: Request, // DMA request number in DMAMUX
         dir: Dir,
@@ -249,16 +255,17 @@ impl AnyChannel {
             dst_addr = core_local_mem_to_sys_address(core_id, dst_addr);
         }
 
-        // configure DMAMUX request and output channel
-        super::dmamux::configure_dmamux(info.mux_num, request);
+        // configure DMAMUX request and output channel, memory to memory transfers are not triggered by requests
+        if dir != Dir::MemoryToMemory {
+            super::dmamux::configure_dmamux(info.mux_num, request);
+        }
 
         ch_cr.src_addr().write_value(src_addr);
         ch_cr.dst_addr().write_value(dst_addr);
         ch_cr.tran_size().modify(|w| w.0 = size_in_words as u32);
+        // linked list transfers set LLPointer after configuring, see `LinkedTransfer`
         ch_cr.llpointer().modify(|w| w.0 = 0x0);
 
-        // TODO: LLPointer handling
-
         self.clear_irqs();
 
         ch_cr.ctrl().write(|w| {
@@ -276,7 +283,7 @@ impl AnyChannel {
 
             if dir == Dir::MemoryToPeripheral {
                 w.set_dstreqsel(mux_ch as u8);
-            } else {
+            } else if dir == Dir::PeripheralToMemory {
                 w.set_srcreqsel(mux_ch as u8);
             }
             // unmask interrupts
@@ -288,7 +295,7 @@ impl AnyChannel {
         });
     }
 
-    fn start(&self) {
+    pub(super) fn start(&self) {
         let info = self.info();
         let r = info.dma.regs();
         let ch = info.num; // channel number in current dma controller
@@ -313,7 +320,7 @@ impl AnyChannel {
     }
 
     // requrest stop
-    fn abort(&self) {
+    pub(super) fn abort(&self) {
         let r = self.info().dma.regs();
 
         r.ch_abort().write(|w| w.set_chabort(self.info().num, true));
@@ -455,6 +462,75 @@ impl<'a> Transfer<'a> {
         )
     }
 
+    /// Create a new memory to memory DMA transfer, copying `src` to `dst`.
+    ///
+    /// The transfer width is the widest allowed by the alignment of both buffers and their length, up to
+    /// double-word on XDMA and word on HDMA. The burst is the largest dividing the transfer count,
+    /// `options.burst` is not used. Empty buffers give a transfer that is already completed.
+    ///
+    /// NOTE: The DMA controller bypasses the L1 data cache. For cacheable buffers, e.g. copies between SDRAM
+    /// and AXI SRAM, clean `src` before the transfer and invalidate `dst` after it, with `andes_riscv::l1c`.
+    pub unsafe fn new_copy(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        src: &'a [u8],
+        dst: &'a mut [u8],
+        options: TransferOptions,
+    ) -> Self {
+        Self::new_copy_raw(channel, src, dst, options)
+    }
+
+    /// Create a new memory to memory DMA transfer, copying `src` to `dst`, using raw pointers.
+    pub unsafe fn new_copy_raw(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        src: *const [u8],
+        dst: *mut [u8],
+        options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+        let channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
+
+        assert_eq!(src.len(), dst.len());
+
+        let src_addr = src as *const u8 as u32;
+        let dst_addr = dst as *mut u8 as u32;
+        let width = super::memory_width(channel.info().dma.max_width(), src_addr, dst_addr, src.len());
+
+        Self::new_memory_inner(
+            channel,
+            src_addr as *const u32,
+            AddrCtrl::INCREMENT,
+            dst_addr as *mut u32,
+            src.len() / width.bytes(),
+            width,
+            options,
+        )
+    }
+
+    /// Create a new memory to memory DMA transfer, filling `dst` with `value`.
+    ///
+    /// The burst is the largest dividing the length of `dst`, `options.burst` is not used.
+    /// An empty `dst` gives a transfer that is already completed.
+    ///
+    /// NOTE: See [`new_copy`](Self::new_copy) for the data cache.
+    pub unsafe fn new_fill<W: Word>(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        value: &'a W,
+        dst: &'a mut [W],
+        options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+
+        Self::new_memory_inner(
+            channel.map_into(),
+            value as *const W as *const u32,
+            AddrCtrl::FIXED,
+            dst.as_mut_ptr() as *mut u32,
+            dst.len(),
+            W::size(),
+            options,
+        )
+    }
+
     // Restrictions comparing to DMA capabilities:
     // - No AddrCtrl::DECREMENT
     unsafe fn new_inner(
@@ -492,6 +568,7 @@ impl<'a> Transfer<'a> {
                 }
                 handshake = HandshakeMode::Source; // source trigger
             }
+            Dir::MemoryToMemory => unreachable!(),
         };
 
         channel.configure(
@@ -512,6 +589,40 @@ impl<'a> Transfer<'a> {
         Self { channel }
     }
 
+    unsafe fn new_memory_inner(
+        channel: PeripheralRef<'a, AnyChannel>,
+        src_addr: *const u32,
+        src_addr_ctrl: AddrCtrl,
+        dst_addr: *mut u32,
+        count: usize,
+        width: WordSize,
+        mut options: TransferOptions,
+    ) -> Self {
+        // nothing to copy, the channel is not started, so the transfer is completed
+        if count == 0 {
+            return Self { channel };
+        }
+
+        options.burst = Burst::from_size(super::memory_burst(count, channel.info().dma.max_burst()));
+
+        channel.configure(
+            0, // no DMAMUX request
+            Dir::MemoryToMemory,
+            src_addr,
+            width,
+            src_addr_ctrl,
+            dst_addr,
+            width,
+            AddrCtrl::INCREMENT,
+            count,
+            HandshakeMode::Normal,
+            options,
+        );
+        channel.start();
+
+        Self { channel }
+    }
+
     /// Request the transfer to stop.
     ///
     /// This doesn't immediately stop the transfer, you have to wait until [`is_running`](Self::is_running) returns false.
@@ -571,7 +682,7 @@ impl<'a> Future for Transfer<'a> {
 }
 
 #[cfg(hpm67)]
-fn core_local_mem_to_sys_address(core_id: u8, addr: u32) -> u32 {
+pub(super) fn core_local_mem_to_sys_address(core_id: u8, addr: u32) -> u32 {
     // const ILM_LOCAL_BASE: u32 = 0x0;
     const ILM_SIZE_IN_BYTE: u32 = 0x40000;
     const DLM_LOCAL_BASE: u32 = 0x80000;
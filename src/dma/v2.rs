@@ -3,14 +3,15 @@
 //! hpm53, hpm68, hpm6e
 #![allow(unused)]
 
-use core::future::Future;
+use core::future::{poll_fn, Future};
 use core::pin::Pin;
-use core::sync::atomic::{fence, AtomicUsize, Ordering};
-use core::task::{Context, Poll};
+use core::sync::atomic::{compiler_fence, fence, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
 
 use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 
+use super::ringbuffer::{DmaCtrl, OverrunError, ReadableDmaRingBuffer, WritableDmaRingBuffer};
 use super::word::{Word, WordSize};
 use super::{AnyChannel, Channel, Dir, Request, STATE};
 use crate::internal::BitIter;
@@ -108,8 +109,8 @@ impl Burst {
 }
 
 pub(crate) struct ChannelState {
-    waker: AtomicWaker,
-    complete_count: AtomicUsize,
+    pub(super) waker: AtomicWaker,
+    pub(super) complete_count: AtomicUsize,
 }
 
 impl ChannelState {
@@ -178,6 +179,12 @@ unsafe fn dma_on_irq(r: pac::dma::Dma, mux_num_base: u32) {
         r.intabortsts().write(|w| w.0 = abort); // W1C
     }
 
+    // complete count is used by circular mode, to detect ring buffer overrun
+    for i in BitIter(tc) {
+        let id = (i + mux_num_base) as usize;
+        STATE[id].complete_count.fetch_add(1, Ordering::Release);
+    }
+
     for i in BitIter(half | tc | abort) {
         let id = (i + mux_num_base) as usize;
         STATE[id].waker.wake();
@@ -215,7 +222,7 @@ impl HandshakeMode {
 }
 
 impl AnyChannel {
-    unsafe fn configure(
+    pub(super) unsafe fn configure(
         &self,
         request: Request, // DMA request number in DMAMUX
         dir: Dir,
@@ -248,23 +255,25 @@ impl AnyChannel {
 
         let ch_cr = r.chctrl(ch);
 
-        // configure DMAMUX request and output channel
-        super::dmamux::configure_dmamux(info.mux_num, request);
+        // configure DMAMUX request and output channel, memory to memory transfers are not triggered by requests
+        if dir != Dir::MemoryToMemory {
+            super::dmamux::configure_dmamux(info.mux_num, request);
+        }
 
         ch_cr.src_addr().write_value(src_addr as u32);
         ch_cr.dst_addr().write_value(dst_addr as u32);
         ch_cr.tran_size().modify(|w| w.0 = size_in_words as u32);
+        // linked list transfers set LLPointer after configuring, see `LinkedTransfer`
         ch_cr.llpointer().modify(|w| w.0 = 0x0);
         ch_cr.chan_req_ctrl().write(|w| {
             if dir == Dir::MemoryToPeripheral {
                 w.set_dstreqsel(mux_ch as u8);
-            } else {
+            } else if dir == Dir::PeripheralToMemory {
                 w.set_srcreqsel(mux_ch as u8);
             }
         });
 
         // TODO: handle SwapTable here
-        // TODO: LLPointer handling
 
         // clear transfer irq status (W1C)
         self.clear_irqs();
@@ -296,7 +305,7 @@ impl AnyChannel {
         });
     }
 
-    fn start(&self) {
+    pub(super) fn start(&self) {
         let info = self.info();
         let r = info.dma.regs();
         let ch = info.num; // channel number in current dma controller
@@ -321,7 +330,7 @@ impl AnyChannel {
     }
 
     // requrest stop
-    fn abort(&self) {
+    pub(super) fn abort(&self) {
         let r = self.info().dma.regs();
 
         r.ch_abort().write(|w| w.set_chabort(self.info().num, true));
@@ -465,6 +474,75 @@ impl<'a> Transfer<'a> {
         )
     }
 
+    /// Create a new memory to memory DMA transfer, copying `src` to `dst`.
+    ///
+    /// The transfer width is the widest allowed by the alignment of both buffers and their length, up to
+    /// double-word on XDMA and word on HDMA. The burst is the largest dividing the transfer count,
+    /// `options.burst` is not used. Empty buffers give a transfer that is already completed.
+    ///
+    /// NOTE: The DMA controller bypasses the L1 data cache. For cacheable buffers, e.g. copies between SDRAM
+    /// and AXI SRAM, clean `src` before the transfer and invalidate `dst` after it, with `andes_riscv::l1c`.
+    pub unsafe fn new_copy(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        src: &'a [u8],
+        dst: &'a mut [u8],
+        options: TransferOptions,
+    ) -> Self {
+        Self::new_copy_raw(channel, src, dst, options)
+    }
+
+    /// Create a new memory to memory DMA transfer, copying `src` to `dst`, using raw pointers.
+    pub unsafe fn new_copy_raw(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        src: *const [u8],
+        dst: *mut [u8],
+        options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+        let channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
+
+        assert_eq!(src.len(), dst.len());
+
+        let src_addr = src as *const u8 as u32;
+        let dst_addr = dst as *mut u8 as u32;
+        let width = super::memory_width(channel.info().dma.max_width(), src_addr, dst_addr, src.len());
+
+        Self::new_memory_inner(
+            channel,
+            src_addr as *const u32,
+            AddrCtrl::INCREMENT,
+            dst_addr as *mut u32,
+            src.len() / width.bytes(),
+            width,
+            options,
+        )
+    }
+
+    /// Create a new memory to memory DMA transfer, filling `dst` with `value`.
+    ///
+    /// The burst is the largest dividing the length of `dst`, `options.burst` is not used.
+    /// An empty `dst` gives a transfer that is already completed.
+    ///
+    /// NOTE: See [`new_copy`](Self::new_copy) for the data cache.
+    pub unsafe fn new_fill<W: Word>(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        value: &'a W,
+        dst: &'a mut [W],
+        options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+
+        Self::new_memory_inner(
+            channel.map_into(),
+            value as *const W as *const u32,
+            AddrCtrl::FIXED,
+            dst.as_mut_ptr() as *mut u32,
+            dst.len(),
+            W::size(),
+            options,
+        )
+    }
+
     // Restrictions comparing to DMA capabilities:
     // - No AddrCtrl::DECREMENT
     unsafe fn new_inner(
@@ -502,6 +580,7 @@ impl<'a> Transfer<'a> {
                 }
                 handshake = HandshakeMode::Source; // source trigger
             }
+            Dir::MemoryToMemory => unreachable!(),
         };
 
         channel.configure(
@@ -522,6 +601,40 @@ impl<'a> Transfer<'a> {
         Self { channel }
     }
 
+    unsafe fn new_memory_inner(
+        channel: PeripheralRef<'a, AnyChannel>,
+        src_addr: *const u32,
+        src_addr_ctrl: AddrCtrl,
+        dst_addr: *mut u32,
+        count: usize,
+        width: WordSize,
+        mut options: TransferOptions,
+    ) -> Self {
+        // nothing to copy, the channel is not started, so the transfer is completed
+        if count == 0 {
+            return Self { channel };
+        }
+
+        options.burst = Burst::from_size(super::memory_burst(count, channel.info().dma.max_burst()));
+
+        channel.configure(
+            0, // no DMAMUX request
+            Dir::MemoryToMemory,
+            src_addr,
+            width,
+            src_addr_ctrl,
+            dst_addr,
+            width,
+            AddrCtrl::INCREMENT,
+            count,
+            HandshakeMode::Normal,
+            options,
+        );
+        channel.start();
+
+        Self { channel }
+    }
+
     /// Request the transfer to stop.
     ///
     /// This doesn't immediately stop the transfer, you have to wait until [`is_running`](Self::is_running) returns false.
@@ -579,3 +692,328 @@ impl<'a> Future for Transfer<'a> {
         }
     }
 }
+
+// ==========
+// ring buffer
+
+struct DmaCtrlImpl<'a>(PeripheralRef<'a, AnyChannel>);
+
+impl<'a> DmaCtrl for DmaCtrlImpl<'a> {
+    fn get_remaining_transfers(&self) -> usize {
+        self.0.get_remaining_transfers() as _
+    }
+
+    fn get_complete_count(&self) -> usize {
+        STATE[self.0.id as usize].complete_count.load(Ordering::Acquire)
+    }
+
+    fn reset_complete_count(&mut self) -> usize {
+        STATE[self.0.id as usize].complete_count.swap(0, Ordering::AcqRel)
+    }
+
+    fn set_waker(&mut self, waker: &Waker) {
+        STATE[self.0.id as usize].waker.register(waker);
+    }
+}
+
+/// Ring buffer for receiving data using DMA circular mode.
+///
+/// The DMA keeps writing words from the peripheral into `buffer`, wrapping around, and wakes the waker
+/// at half and full of the buffer. Data not read before the DMA comes back to it is lost, reported by
+/// [`OverrunError`].
+///
+/// A peripheral driver using it:
+/// - creates it with the RX channel, its DMAMUX request and the peripheral data register,
+/// - calls [`start`](Self::start), then enables the DMA request of the peripheral,
+/// - reads with [`read`](Self::read), waiting on [`set_waker`](Self::set_waker) together with its own events,
+///   or with [`read_exact`](Self::read_exact),
+/// - on [`OverrunError`], or to stop, disables the DMA request of the peripheral, then calls
+///   [`request_stop`](Self::request_stop) and waits for [`is_running`](Self::is_running) to return false.
+///   A new [`start`](Self::start) drops the unread data.
+pub struct ReadableRingBuffer<'a, W: Word> {
+    channel: PeripheralRef<'a, AnyChannel>,
+    request: Request,
+    peri_addr: *mut W,
+    options: TransferOptions,
+    ringbuf: ReadableDmaRingBuffer<'a, W>,
+}
+
+impl<'a, W: Word> ReadableRingBuffer<'a, W> {
+    /// Create a new ring buffer, the transfer is not started until [`start`](Self::start) is called.
+    pub unsafe fn new(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        request: Request,
+        peri_addr: *mut W,
+        buffer: &'a mut [W],
+        mut options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+
+        assert!(buffer.len() > 0);
+
+        options.circular = true;
+        options.half_transfer_irq = true;
+        options.complete_transfer_irq = true;
+
+        Self {
+            channel: channel.map_into(),
+            request,
+            peri_addr,
+            options,
+            ringbuf: ReadableDmaRingBuffer::new(buffer),
+        }
+    }
+
+    /// Start the ring buffer operation.
+    ///
+    /// The DMA buffer is reset, all unread data is dropped.
+    pub fn start(&mut self) {
+        let len = self.ringbuf.cap();
+        let buf = self.ringbuf.dma_buf.as_mut_ptr();
+
+        unsafe {
+            self.channel.configure(
+                self.request,
+                Dir::PeripheralToMemory,
+                self.peri_addr as *const u32,
+                W::size(),
+                AddrCtrl::FIXED,
+                buf as *mut u32,
+                W::size(),
+                AddrCtrl::INCREMENT,
+                len,
+                HandshakeMode::Source,
+                self.options,
+            );
+        }
+        self.ringbuf.clear(&mut DmaCtrlImpl(self.channel.reborrow()));
+
+        self.channel.start();
+    }
+
+    /// Clear all data in the ring buffer.
+    pub fn clear(&mut self) {
+        self.ringbuf.clear(&mut DmaCtrlImpl(self.channel.reborrow()));
+    }
+
+    /// Read elements from the ring buffer
+    ///
+    /// Return a tuple of the length read and the length remaining in the buffer.
+    /// If not all of the elements were read, then there will be some elements in the buffer remaining.
+    /// The length remaining is the capacity, ring_buf.len(), less the elements remaining after the read.
+    /// OverrunError is returned if the portion to be read was overwritten by the DMA controller.
+    pub fn read(&mut self, buf: &mut [W]) -> Result<(usize, usize), OverrunError> {
+        self.ringbuf.read(&mut DmaCtrlImpl(self.channel.reborrow()), buf)
+    }
+
+    /// Read an exact number of elements from the ring buffer, waiting for the DMA.
+    ///
+    /// The DMA wakes at half and full of the buffer, so the wait can be up to half of the capacity
+    /// longer than needed for `buf`.
+    pub async fn read_exact(&mut self, buf: &mut [W]) -> Result<(), OverrunError> {
+        let mut read = 0;
+
+        poll_fn(|cx| {
+            self.set_waker(cx.waker());
+
+            compiler_fence(Ordering::SeqCst);
+
+            match self.read(&mut buf[read..]) {
+                Ok((len, _)) => {
+                    read += len;
+                    if read == buf.len() {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        Poll::Pending
+                    }
+                }
+                Err(e) => Poll::Ready(Err(e)),
+            }
+        })
+        .await
+    }
+
+    /// The capacity of the ring buffer.
+    pub const fn capacity(&self) -> usize {
+        self.ringbuf.cap()
+    }
+
+    /// Set a waker to be woken when at least half of the buffer, or the whole buffer, is filled.
+    pub fn set_waker(&mut self, waker: &Waker) {
+        DmaCtrlImpl(self.channel.reborrow()).set_waker(waker);
+    }
+
+    /// Request the DMA to stop.
+    ///
+    /// This doesn't immediately stop the transfer, you have to wait until [`is_running`](Self::is_running) returns false.
+    pub fn request_stop(&mut self) {
+        self.channel.abort();
+    }
+
+    /// Return whether DMA is still running.
+    ///
+    /// If this returns `false`, it can be because either the transfer finished, or
+    /// it was requested to stop early with [`request_stop`](Self::request_stop).
+    pub fn is_running(&mut self) -> bool {
+        self.channel.is_running()
+    }
+}
+
+impl<'a, W: Word> Drop for ReadableRingBuffer<'a, W> {
+    fn drop(&mut self) {
+        self.request_stop();
+        while self.is_running() {}
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Ring buffer for sending data using DMA circular mode.
+///
+/// The DMA keeps sending words from `buffer` to the peripheral, wrapping around, and wakes the waker
+/// at half and full of the buffer. The whole buffer is sent in the first round after [`start`](Self::start),
+/// so it should be filled before creating the ring buffer. If the DMA comes back to words that were
+/// not written again, it sends stale data, reported by [`OverrunError`].
+///
+/// A peripheral driver using it:
+/// - creates it with the TX channel, its DMAMUX request and the peripheral data register,
+/// - calls [`start`](Self::start), then enables the DMA request of the peripheral,
+/// - writes with [`write`](Self::write), waiting on [`set_waker`](Self::set_waker) together with its own events,
+///   or with [`write_exact`](Self::write_exact),
+/// - on [`OverrunError`], or to stop, disables the DMA request of the peripheral, then calls
+///   [`request_stop`](Self::request_stop) and waits for [`is_running`](Self::is_running) to return false.
+pub struct WritableRingBuffer<'a, W: Word> {
+    channel: PeripheralRef<'a, AnyChannel>,
+    request: Request,
+    peri_addr: *mut W,
+    options: TransferOptions,
+    ringbuf: WritableDmaRingBuffer<'a, W>,
+}
+
+impl<'a, W: Word> WritableRingBuffer<'a, W> {
+    /// Create a new ring buffer, the transfer is not started until [`start`](Self::start) is called.
+    pub unsafe fn new(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        request: Request,
+        peri_addr: *mut W,
+        buffer: &'a mut [W],
+        mut options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+
+        assert!(buffer.len() > 0);
+
+        options.circular = true;
+        options.half_transfer_irq = true;
+        options.complete_transfer_irq = true;
+
+        Self {
+            channel: channel.map_into(),
+            request,
+            peri_addr,
+            options,
+            ringbuf: WritableDmaRingBuffer::new(buffer),
+        }
+    }
+
+    /// Start the ring buffer operation.
+    ///
+    /// The whole DMA buffer is sent in the first round, with its current content.
+    pub fn start(&mut self) {
+        let len = self.ringbuf.cap();
+        let buf = self.ringbuf.dma_buf.as_mut_ptr();
+
+        unsafe {
+            self.channel.configure(
+                self.request,
+                Dir::MemoryToPeripheral,
+                buf as *const u32,
+                W::size(),
+                AddrCtrl::INCREMENT,
+                self.peri_addr as *mut u32,
+                W::size(),
+                AddrCtrl::FIXED,
+                len,
+                HandshakeMode::Destination,
+                self.options,
+            );
+        }
+        self.ringbuf.clear(&mut DmaCtrlImpl(self.channel.reborrow()));
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        fence(Ordering::SeqCst);
+
+        self.channel.start();
+    }
+
+    /// Write elements to the ring buffer
+    ///
+    /// Return a tuple of the length written and the free space remaining in the buffer.
+    /// OverrunError is returned if the DMA controller has sent elements that were not written yet.
+    pub fn write(&mut self, buf: &[W]) -> Result<(usize, usize), OverrunError> {
+        self.ringbuf.write(&mut DmaCtrlImpl(self.channel.reborrow()), buf)
+    }
+
+    /// Write an exact number of elements to the ring buffer, waiting for free space.
+    ///
+    /// The DMA wakes at half and full of the buffer, so the wait can be up to half of the capacity
+    /// longer than needed for `buf`.
+    pub async fn write_exact(&mut self, buf: &[W]) -> Result<(), OverrunError> {
+        let mut written = 0;
+
+        poll_fn(|cx| {
+            self.set_waker(cx.waker());
+
+            compiler_fence(Ordering::SeqCst);
+
+            match self.write(&buf[written..]) {
+                Ok((len, _)) => {
+                    written += len;
+                    if written == buf.len() {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        Poll::Pending
+                    }
+                }
+                Err(e) => Poll::Ready(Err(e)),
+            }
+        })
+        .await
+    }
+
+    /// The capacity of the ring buffer.
+    pub const fn capacity(&self) -> usize {
+        self.ringbuf.cap()
+    }
+
+    /// Set a waker to be woken when at least half of the buffer, or the whole buffer, is sent.
+    pub fn set_waker(&mut self, waker: &Waker) {
+        DmaCtrlImpl(self.channel.reborrow()).set_waker(waker);
+    }
+
+    /// Request the DMA to stop.
+    ///
+    /// This doesn't immediately stop the transfer, you have to wait until [`is_running`](Self::is_running) returns false.
+    pub fn request_stop(&mut self) {
+        self.channel.abort();
+    }
+
+    /// Return whether DMA is still running.
+    ///
+    /// If this returns `false`, it can be because either the transfer finished, or
+    /// it was requested to stop early with [`request_stop`](Self::request_stop).
+    pub fn is_running(&mut self) -> bool {
+        self.channel.is_running()
+    }
+}
+
+impl<'a, W: Word> Drop for WritableRingBuffer<'a, W> {
+    fn drop(&mut self) {
+        self.request_stop();
+        while self.is_running() {}
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        fence(Ordering::SeqCst);
+    }
+}
@@ -69,7 +69,7 @@ macro_rules! impl_word {
     };
     ($T:ident, $uX:ident, $bits:literal, $size:ident) => {
         #[repr(transparent)]
-        #[derive(Copy, Clone, Default)]
+        #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
         #[doc = concat!(stringify!($T), " word size")]
         pub struct $T(pub $uX);
         impl_word!(_, $T, $bits, $size);
@@ -1,4 +1,6 @@
 //! XPI Flash memory (XPI Nor API)
+//!
+//! See [`xpi`] for other memory-mapped XPI devices.
 
 use core::marker::PhantomData;
 
@@ -13,6 +15,7 @@ use crate::peripherals;
 
 #[allow(non_camel_case_types, non_snake_case, non_upper_case_globals, unused)]
 mod romapi;
+pub mod xpi;
 
 const ROM_API_TABLE_ROOT: *const romapi::bootloader_api_table_t = 0x2001FF00 as *const romapi::bootloader_api_table_t;
 
@@ -232,6 +235,8 @@ fn rom_xpi_nor_get_property(xpi_base: *mut u32, nor_cfg: &xpi_nor_config_t, prop
 
 trait SealedInstance {
     const ADDR_OFFSET: u32;
+    /// Whether the boot flash is on this XPI, already initialized by the boot ROM
+    const BOOT_DEVICE: bool;
 
     const REGS: crate::pac::xpi::Xpi;
 }
@@ -242,6 +247,7 @@ pub trait Instance: SealedInstance {}
 
 impl SealedInstance for peripherals::XPI0 {
     const ADDR_OFFSET: u32 = 0x8000_0000;
+    const BOOT_DEVICE: bool = true;
     const REGS: crate::pac::xpi::Xpi = crate::pac::XPI0;
 }
 impl Instance for peripherals::XPI0 {}
@@ -249,6 +255,7 @@ impl Instance for peripherals::XPI0 {}
 #[cfg(peri_xpi1)]
 impl SealedInstance for peripherals::XPI1 {
     const ADDR_OFFSET: u32 = 0x9000_0000;
+    const BOOT_DEVICE: bool = false;
     const REGS: crate::pac::xpi::Xpi = crate::pac::XPI1;
 }
 #[cfg(peri_xpi1)]
@@ -0,0 +1,437 @@
+//! Memory-mapped XPI devices (XPI API)
+//!
+//! Sets up a device other than the boot flash for memory-mapped access, e.g. QSPI PSRAM such as APS6404,
+//! or a data flash on XPI1, using the ROM XPI driver.
+//!
+//! The device is accessed by the AHB read and write instruction sequences, built with [`InstrSeq`].
+//! E.g. for APS6404 in SPI mode:
+//! - read: `Cmd(1 pad, 0xEB)`, `RowAddr(4 pads, 24 bits)`, `Dummy(4 pads, 6 cycles)`, `Read(4 pads)`
+//! - write: `Cmd(1 pad, 0x38)`, `RowAddr(4 pads, 24 bits)`, `Write(4 pads)`
+//!
+//! NOTE: The XPI pins must be configured by the application.
+//! When a device is added to XPI0, the boot flash keeps working, but the instruction table slots
+//! used by the NOR driver must be avoided, see [`DeviceConfig::seq_idx`].
+//!
+//! NOTE: The ROM calls reconfiguring the controller run from RAM, with interrupts disabled, because
+//! code can't be fetched from XPI0 (XIP) meanwhile. This needs the `.fast` section of the linker script,
+//! as provided by `hpm-riscv-rt`.
+
+use core::marker::PhantomData;
+
+use embassy_hal_internal::{into_ref, Peripheral};
+
+use super::romapi::{
+    xpi_ahb_buffer_cfg_t, xpi_ahb_buffer_cfg_t__bindgen_ty_1, xpi_channel_a1, xpi_channel_a2, xpi_channel_b1,
+    xpi_channel_b2, xpi_channel_t, xpi_config_t, xpi_device_config_t, xpi_xfer_channel_a1, xpi_xfer_channel_a2,
+    xpi_xfer_channel_b1, xpi_xfer_channel_b2, xpi_xfer_channel_t, XPI_Type,
+};
+use super::{Error, Instance, ROM_API_TABLE_ROOT};
+use crate::sysctl::ClockPeripheral;
+
+/// Number of instructions in a sequence
+const INSTRS_PER_SEQ: usize = 8;
+
+/// XPI channel, the port a device is connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Port A, CS 1
+    A1,
+    /// Port A, CS 2
+    A2,
+    /// Port B, CS 1
+    B1,
+    /// Port B, CS 2
+    B2,
+}
+
+impl Channel {
+    fn to_raw(self) -> xpi_channel_t {
+        match self {
+            Channel::A1 => xpi_channel_a1,
+            Channel::A2 => xpi_channel_a2,
+            Channel::B1 => xpi_channel_b1,
+            Channel::B2 => xpi_channel_b2,
+        }
+    }
+
+    fn to_xfer_channel(self) -> xpi_xfer_channel_t {
+        match self {
+            Channel::A1 => xpi_xfer_channel_a1,
+            Channel::A2 => xpi_xfer_channel_a2,
+            Channel::B1 => xpi_xfer_channel_b1,
+            Channel::B2 => xpi_xfer_channel_b2,
+        }
+    }
+}
+
+// - MARK: Instruction sequence
+
+/// Phase of an XPI instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Phase {
+    /// End of sequence
+    Stop = 0x00,
+    /// Command, the operand is the opcode
+    Cmd = 0x01,
+    /// Row address, the operand is the address bits
+    RowAddr = 0x02,
+    /// Column address, the operand is the address bits
+    ColumnAddr = 0x03,
+    /// 4-bit mode bits, the operand is the mode bits
+    Mode4 = 0x06,
+    /// 8-bit mode bits, the operand is the mode bits
+    Mode8 = 0x07,
+    /// Write data
+    Write = 0x08,
+    /// Read data
+    Read = 0x09,
+    /// Dummy, the operand is the dummy cycles
+    Dummy = 0x0C,
+    /// Dummy, with RWDS signal for HyperBus devices
+    DummyRwds = 0x0D,
+}
+
+/// Number of data pads of an XPI instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Pads {
+    /// Single SPI
+    One = 0,
+    /// Dual SPI
+    Two = 1,
+    /// Quad SPI
+    Four = 2,
+    /// Octal SPI
+    Eight = 3,
+}
+
+/// XPI instruction, a phase with its pads and operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instr(u16);
+
+impl Instr {
+    /// End of sequence
+    pub const STOP: Self = Self(0);
+
+    /// Create an SDR instruction.
+    pub const fn new(phase: Phase, pads: Pads, operand: u8) -> Self {
+        Self(((phase as u16) << 10) | ((pads as u16) << 8) | operand as u16)
+    }
+
+    /// Make this instruction DDR.
+    pub const fn ddr(self) -> Self {
+        Self(self.0 | (0x20 << 10))
+    }
+
+    /// Command phase.
+    pub const fn cmd(pads: Pads, opcode: u8) -> Self {
+        Self::new(Phase::Cmd, pads, opcode)
+    }
+
+    /// Row address phase, of `bits` address bits.
+    pub const fn addr(pads: Pads, bits: u8) -> Self {
+        Self::new(Phase::RowAddr, pads, bits)
+    }
+
+    /// Dummy phase, of `cycles` dummy cycles.
+    pub const fn dummy(pads: Pads, cycles: u8) -> Self {
+        Self::new(Phase::Dummy, pads, cycles)
+    }
+
+    /// Read data phase.
+    pub const fn read(pads: Pads) -> Self {
+        Self::new(Phase::Read, pads, 0x04)
+    }
+
+    /// Write data phase.
+    pub const fn write(pads: Pads) -> Self {
+        Self::new(Phase::Write, pads, 0x04)
+    }
+}
+
+/// XPI instruction sequence, up to 8 instructions, one slot of the instruction table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrSeq {
+    instrs: [Instr; INSTRS_PER_SEQ],
+    len: usize,
+}
+
+impl InstrSeq {
+    /// Create an empty sequence.
+    pub const fn new() -> Self {
+        Self {
+            instrs: [Instr::STOP; INSTRS_PER_SEQ],
+            len: 0,
+        }
+    }
+
+    /// Append an instruction.
+    ///
+    /// Panics if the sequence is full.
+    pub const fn then(mut self, instr: Instr) -> Self {
+        assert!(self.len < INSTRS_PER_SEQ, "XPI instruction sequence is full");
+        self.instrs[self.len] = instr;
+        self.len += 1;
+        self
+    }
+
+    /// The raw instruction table entry.
+    pub const fn to_raw(&self) -> [u32; 4] {
+        let mut raw = [0u32; 4];
+        let mut i = 0;
+        while i < 4 {
+            raw[i] = (self.instrs[i * 2].0 as u32) | ((self.instrs[i * 2 + 1].0 as u32) << 16);
+            i += 1;
+        }
+        raw
+    }
+}
+
+impl Default for InstrSeq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// - MARK: Config
+
+/// AHB buffer config, one for each AHB master
+#[derive(Debug, Clone, Copy)]
+pub struct AhbBuffer {
+    /// Arbitration priority of the buffer
+    pub priority: u8,
+    /// Index of the AHB master using the buffer
+    pub master_idx: u8,
+    /// Buffer size, in 64-bit words
+    pub size_in_dwords: u8,
+    /// Prefetch the next data into the buffer
+    pub prefetch: bool,
+}
+
+/// XPI device config
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceConfig {
+    /// Device size in bytes
+    pub size: u32,
+    /// AHB read sequence
+    pub read_seq: InstrSeq,
+    /// AHB write sequence, `None` for read-only devices
+    pub write_seq: Option<InstrSeq>,
+    /// First instruction table slot used, the read sequence is at `seq_idx`, the write sequence at `seq_idx + 1`.
+    ///
+    /// The default, 14, leaves the slots used by the NOR driver of the boot flash.
+    pub seq_idx: u8,
+    /// Write mask, typically for PSRAM
+    pub enable_write_mask: bool,
+    /// Data valid time, unit 0.1ns
+    pub data_valid_time: u8,
+    /// CS hold time, in serial clock cycles
+    pub cs_hold_time: u8,
+    /// CS setup time, in serial clock cycles
+    pub cs_setup_time: u8,
+    /// CS interval, in serial clock cycles
+    pub cs_interval: u16,
+    /// AHB write wait interval, in serial clock cycles
+    pub ahb_write_wait_interval: u8,
+    /// AHB buffers, `None` to keep the current config
+    pub ahb_buffers: Option<[AhbBuffer; 8]>,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            read_seq: InstrSeq::new(),
+            write_seq: None,
+            seq_idx: 14,
+            enable_write_mask: false,
+            data_valid_time: 0,
+            cs_hold_time: 3,
+            cs_setup_time: 3,
+            cs_interval: 0,
+            ahb_write_wait_interval: 0,
+            ahb_buffers: None,
+        }
+    }
+}
+
+// - MARK: XPI memory driver
+
+/// Memory-mapped XPI device.
+pub struct XpiMemory<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    addr: u32,
+    size: u32,
+}
+
+impl<'d, T: Instance + ClockPeripheral> XpiMemory<'d, T> {
+    /// Configure a device on `channel` for memory-mapped access.
+    ///
+    /// The XPI controller is initialized first, except for XPI0, which is already used by the boot flash.
+    /// Channel A1 of XPI0 is the boot flash the code runs from, it is rejected with [`Error::InvalidArgument`].
+    pub fn new(xpi: impl Peripheral<P = T> + 'd, channel: Channel, config: DeviceConfig) -> Result<Self, Error> {
+        into_ref!(xpi);
+        let _ = xpi;
+
+        if T::BOOT_DEVICE && channel == Channel::A1 {
+            return Err(Error::InvalidArgument);
+        }
+        if config.size == 0 || config.read_seq.len == 0 || config.seq_idx > 14 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let xpi_driver = unsafe { &*(*ROM_API_TABLE_ROOT).xpi_driver_if };
+        let base = T::REGS.as_ptr() as *mut _;
+
+        if !T::BOOT_DEVICE {
+            let mut xpi_config: xpi_config_t = unsafe { core::mem::zeroed() };
+            Error::chect_status(unsafe { xpi_driver.get_default_config.unwrap()(&mut xpi_config) })?;
+            Error::chect_status(unsafe { xpi_driver.init.unwrap()(base, &mut xpi_config) })?;
+        }
+
+        // instruction table
+        let mut seqs = [[0u32; 4]; 2];
+        seqs[0] = config.read_seq.to_raw();
+        let num_seqs = match config.write_seq {
+            Some(write_seq) => {
+                seqs[1] = write_seq.to_raw();
+                2
+            }
+            None => 1,
+        };
+
+        // device
+        let mut dev_config: xpi_device_config_t = unsafe { core::mem::zeroed() };
+        Error::chect_status(unsafe { xpi_driver.get_default_device_config.unwrap()(&mut dev_config) })?;
+
+        dev_config.size_in_kbytes = config.size / 1024;
+        dev_config.serial_root_clk_freq = T::frequency().0;
+        dev_config.enable_write_mask = config.enable_write_mask as u8;
+        dev_config.data_valid_time = config.data_valid_time;
+        dev_config.cs_hold_time = config.cs_hold_time;
+        dev_config.cs_setup_time = config.cs_setup_time;
+        dev_config.cs_interval = config.cs_interval;
+        dev_config.ahb_read_seq_idx = config.seq_idx;
+        dev_config.ahb_read_seq_num = 1;
+        if config.write_seq.is_some() {
+            dev_config.ahb_write_seq_idx = config.seq_idx + 1;
+            dev_config.ahb_write_seq_num = 1;
+        }
+        dev_config.ahb_write_wait_interval = config.ahb_write_wait_interval;
+
+        let mut ahb_config = config.ahb_buffers.map(|buffers| xpi_ahb_buffer_cfg_t {
+            entry: buffers.map(|b| xpi_ahb_buffer_cfg_t__bindgen_ty_1 {
+                priority: b.priority,
+                master_idx: b.master_idx,
+                buf_size_in_dword: b.size_in_dwords,
+                enable_prefetch: b.prefetch,
+            }),
+        });
+
+        let rom = RomFns {
+            update_instr_table: xpi_driver.update_instr_table.unwrap(),
+            config_device: xpi_driver.config_device.unwrap(),
+            config_ahb_buffer: xpi_driver.config_ahb_buffer.unwrap(),
+        };
+        let ahb_config = match ahb_config.as_mut() {
+            Some(ahb_config) => ahb_config as *mut _,
+            None => core::ptr::null_mut(),
+        };
+        Error::chect_status(critical_section::with(|_| unsafe {
+            rom_configure_device(
+                &rom,
+                base,
+                seqs.as_ptr() as *const u32,
+                config.seq_idx as u32,
+                num_seqs,
+                &mut dev_config,
+                channel.to_raw(),
+                ahb_config,
+            )
+        }))?;
+
+        // the channel offset in the XPI address space
+        let mut offset = 0;
+        Error::chect_status(unsafe {
+            xpi_driver.get_abs_apb_xfer_addr.unwrap()(base, channel.to_xfer_channel(), 0, &mut offset)
+        })?;
+
+        Ok(Self {
+            phantom: PhantomData,
+            addr: T::ADDR_OFFSET + offset,
+            size: config.size,
+        })
+    }
+}
+
+impl<'d, T: Instance> XpiMemory<'d, T> {
+    /// Start address of the device in the memory map.
+    pub fn address(&self) -> u32 {
+        self.addr
+    }
+
+    /// Device size in bytes.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Raw pointer to the device memory.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.addr as *mut u8
+    }
+
+    /// The device memory as a slice.
+    ///
+    /// NOTE: Accesses go through the L1 data cache, use `andes_riscv::l1c` to maintain it
+    /// when the memory is shared with DMA or another bus master.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.size()) }
+    }
+
+    /// The device memory as a mutable slice.
+    ///
+    /// See [`as_slice`](Self::as_slice).
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.size()) }
+    }
+}
+
+// - MARK: ROMAPI fn
+
+/// ROM functions reconfiguring the controller, resolved before [`rom_configure_device`].
+struct RomFns {
+    update_instr_table: unsafe extern "C" fn(*mut XPI_Type, *const u32, u32, u32) -> u32,
+    config_device: unsafe extern "C" fn(*mut XPI_Type, *mut xpi_device_config_t, xpi_channel_t) -> u32,
+    config_ahb_buffer: unsafe extern "C" fn(*mut XPI_Type, *mut xpi_ahb_buffer_cfg_t) -> u32,
+}
+
+/// Update the instruction table, configure the device and the AHB buffers, `ahb_config` may be null.
+///
+/// Runs from RAM, and must be called with interrupts disabled: while XPI0 is reconfigured, no code
+/// can be fetched from the boot flash. So only plain calls through the ROM function pointers here.
+#[link_section = ".fast"]
+#[inline(never)]
+unsafe fn rom_configure_device(
+    rom: &RomFns,
+    base: *mut XPI_Type,
+    seqs: *const u32,
+    seq_idx: u32,
+    num_seqs: u32,
+    dev_config: *mut xpi_device_config_t,
+    channel: xpi_channel_t,
+    ahb_config: *mut xpi_ahb_buffer_cfg_t,
+) -> u32 {
+    let status = (rom.update_instr_table)(base, seqs, seq_idx, num_seqs);
+    if status != 0 {
+        return status;
+    }
+
+    let status = (rom.config_device)(base, dev_config, channel);
+    // no `is_null`, it may not be inlined
+    if status != 0 || ahb_config as usize == 0 {
+        return status;
+    }
+
+    (rom.config_ahb_buffer)(base, ahb_config)
+}
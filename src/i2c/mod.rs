@@ -1,6 +1,5 @@
 //! I2C
 
-use core::future::Future;
 use core::marker::PhantomData;
 use core::task::Poll;
 
@@ -13,11 +12,17 @@ use hpm_metapac::i2c::vals;
 
 use crate::dma::ChannelAndRequest;
 use crate::gpio::AnyPin;
+use crate::internal::timeout::{Elapsed, Timeout};
 use crate::interrupt::typelevel::Interrupt as _;
 use crate::mode::{Async, Blocking, Mode};
 use crate::time::Hertz;
 use crate::{interrupt, peripherals};
 
+mod recovery;
+mod slave;
+pub mod smbus;
+pub use slave::*;
+
 const HPM_I2C_DRV_DEFAULT_TPM: i32 = 0;
 
 // family specific features
@@ -45,6 +50,10 @@ pub unsafe fn on_interrupt<T: Instance>() {
         w.set_cmpl(false);
         w.set_arblose(false);
         w.set_stop(false);
+        // slave mode
+        w.set_addrhit(false);
+        w.set_fifoempty(false);
+        w.set_fifofull(false);
     });
 
     T::state().waker.wake();
@@ -77,6 +86,39 @@ pub enum I2cMode {
     FastPlus,
 }
 
+/// I2C address, 7-bit or 10-bit.
+///
+/// 10-bit addresses are used through the `embedded_hal::i2c::I2c<TenBitAddress>` traits, or as own address in slave mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Address {
+    /// 7-bit address
+    SevenBit(u8),
+    /// 10-bit address
+    TenBit(u16),
+}
+
+impl From<u8> for Address {
+    fn from(addr: u8) -> Self {
+        Address::SevenBit(addr)
+    }
+}
+
+impl Address {
+    fn is_ten_bit(&self) -> bool {
+        matches!(self, Address::TenBit(_))
+    }
+
+    /// The address register value, [`Error::InvalidArgument`] if the address is out of range.
+    fn raw(&self) -> Result<u16, Error> {
+        match *self {
+            Address::SevenBit(addr) if addr <= 0x7f => Ok(addr as u16),
+            Address::TenBit(addr) if addr <= 0x3ff => Ok(addr),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
+
 /// I2C error.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -103,11 +145,49 @@ pub enum Error {
     InvalidArgument,
 }
 
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        Error::Timeout
+    }
+}
+
+/// I2C config error
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// Frequency too high for the mode, or for the rise and fall times
+    FrequencyTooHigh,
+    /// Frequency too low for the kernel clock
+    FrequencyTooLow,
+    /// Setup or hold time out of range, for the kernel clock
+    TimingOutOfRange,
+    /// Own address out of range, in slave mode
+    InvalidAddress,
+}
+
 /// I2C config
 #[non_exhaustive]
 #[derive(Copy, Clone)]
 pub struct Config {
+    /// Mode, for the min SCL high and low periods and data setup and hold times.
     pub mode: I2cMode,
+    /// SCL frequency, `None` for the max frequency of the mode.
+    ///
+    /// Must not be higher than the max frequency of the mode.
+    pub frequency: Option<Hertz>,
+    /// SCL and SDA rise time in ns, depends on the pull-up and the bus capacitance.
+    ///
+    /// Deducted from the SCL period, and added to the data setup time.
+    pub rise_time_ns: u32,
+    /// SCL and SDA fall time in ns. Deducted from the SCL period.
+    pub fall_time_ns: u32,
+    /// Recover the bus when it is stuck busy, see [`I2c::recover_bus`]. Disabled by default.
+    ///
+    /// A stuck bus is detected by the timeout of the idle wait before a transaction. Without the `time` feature,
+    /// the blocking API gives up waiting after 100ms, the async API waits forever for a busy bus,
+    /// and only recovers SDA held low on an idle bus.
+    pub bus_recovery: bool,
     /// Timeout.
     #[cfg(feature = "time")]
     pub timeout: embassy_time::Duration,
@@ -117,6 +197,10 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             mode: I2cMode::Standard,
+            frequency: None,
+            rise_time_ns: 0,
+            fall_time_ns: 0,
+            bus_recovery: false,
             #[cfg(feature = "time")]
             timeout: embassy_time::Duration::from_millis(1000),
         }
@@ -132,6 +216,8 @@ pub struct I2c<'d, M: Mode> {
     scl: Option<PeripheralRef<'d, AnyPin>>,
     sda: Option<PeripheralRef<'d, AnyPin>>,
     dma: Option<ChannelAndRequest<'d>>,
+    timing: Timings,
+    bus_recovery: bool,
     #[cfg(feature = "time")]
     timeout: embassy_time::Duration,
     _phantom: PhantomData<M>,
@@ -144,7 +230,7 @@ impl<'d> I2c<'d, Blocking> {
         scl: impl Peripheral<P = impl SclPin<T>> + 'd,
         sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
         into_ref!(scl, sda);
 
         scl.set_as_ioc_gpio();
@@ -223,7 +309,7 @@ impl<'d> I2c<'d, Async> {
         _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
         dma: impl Peripheral<P = impl I2cDma<T>> + 'd,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
         into_ref!(scl, sda);
 
         scl.set_as_ioc_gpio();
@@ -293,14 +379,10 @@ impl<'d> I2c<'d, Async> {
 
     /// Write.
     pub async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Error> {
-        if self.info.regs.status().read().busbusy() {
-            let timeout = self.timeout();
-            let fut = self.wait_for_stop();
-            timeout.with(fut).await?;
-        }
+        self.wait_idle().await?;
 
         self.do_operation_inner(
-            address,
+            Address::SevenBit(address),
             &mut Operation::Write(write),
             FrameOptions {
                 send_start: true,
@@ -313,14 +395,10 @@ impl<'d> I2c<'d, Async> {
 
     /// Read.
     pub async fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
-        if self.info.regs.status().read().busbusy() {
-            let timeout = self.timeout();
-            let fut = self.wait_for_stop();
-            timeout.with(fut).await?;
-        }
+        self.wait_idle().await?;
 
         self.do_operation_inner(
-            address,
+            Address::SevenBit(address),
             &mut Operation::Read(buffer),
             FrameOptions {
                 send_start: true,
@@ -339,14 +417,10 @@ impl<'d> I2c<'d, Async> {
             return Err(Error::Overrun);
         }
 
-        if self.info.regs.status().read().busbusy() {
-            let timeout = self.timeout();
-            let fut = self.wait_for_stop();
-            timeout.with(fut).await?;
-        }
+        self.wait_idle().await?;
 
         self.do_operation_inner(
-            address,
+            Address::SevenBit(address),
             &mut Operation::Write(write),
             FrameOptions {
                 send_start: true,
@@ -356,7 +430,7 @@ impl<'d> I2c<'d, Async> {
         )
         .await?;
         self.do_operation_inner(
-            address,
+            Address::SevenBit(address),
             &mut Operation::Read(read),
             FrameOptions {
                 send_start: true,
@@ -375,11 +449,11 @@ impl<'d> I2c<'d, Async> {
     ///
     /// [transaction contract]: embedded_hal::i2c::I2c::transaction
     pub async fn transaction(&mut self, addr: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
-        if self.info.regs.status().read().busbusy() {
-            let timeout = self.timeout();
-            let fut = self.wait_for_stop();
-            timeout.with(fut).await?;
-        }
+        self.transaction_inner(Address::SevenBit(addr), operations).await
+    }
+
+    async fn transaction_inner(&mut self, addr: Address, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        self.wait_idle().await?;
 
         for (op, frame) in operation_frames(operations)? {
             self.do_operation_inner(addr, op, frame).await?;
@@ -411,7 +485,12 @@ impl<'d> I2c<'d, Async> {
         Ok(())
     }
 
-    async fn do_operation_inner(&mut self, addr: u8, op: &mut Operation<'_>, frame: FrameOptions) -> Result<(), Error> {
+    async fn do_operation_inner(
+        &mut self,
+        addr: Address,
+        op: &mut Operation<'_>,
+        frame: FrameOptions,
+    ) -> Result<(), Error> {
         let r = self.info.regs;
 
         let (size, dir) = match op {
@@ -425,7 +504,7 @@ impl<'d> I2c<'d, Async> {
 
         // W1C, clear CMPL bit to avoid blocking the transmission
         r.status().write(|w| w.set_cmpl(true));
-        r.addr().write(|w| w.set_addr(addr as u16));
+        set_address(r, addr)?;
         r.ctrl().write(|w| {
             w.set_phase_start(frame.send_start);
             w.set_phase_stop(frame.send_stop);
@@ -444,10 +523,14 @@ impl<'d> I2c<'d, Async> {
             w.set_arblose(true);
         });
 
+        // no data phase, e.g. probe or SMBus quick command
         let ch = self.dma.as_mut().unwrap();
         let transfer = match op {
-            Operation::Read(read) => unsafe { ch.read(r.data().as_ptr() as *mut u8, read, Default::default()) },
-            Operation::Write(write) => unsafe { ch.write(write, r.data().as_ptr() as *mut u8, Default::default()) },
+            _ if size == 0 => None,
+            Operation::Read(read) => Some(unsafe { ch.read(r.data().as_ptr() as *mut u8, read, Default::default()) }),
+            Operation::Write(write) => {
+                Some(unsafe { ch.write(write, r.data().as_ptr() as *mut u8, Default::default()) })
+            }
         };
 
         let on_drop = OnDrop::new(|| {
@@ -462,7 +545,9 @@ impl<'d> I2c<'d, Async> {
         r.setup().modify(|w| w.set_dmaen(true));
         r.cmd().write(|w| w.set_cmd(vals::Cmd::DATA_TRANSACTION));
 
-        transfer.await;
+        if let Some(transfer) = transfer {
+            transfer.await;
+        }
 
         let s = self.state;
 
@@ -494,7 +579,9 @@ impl<'d, M: Mode> I2c<'d, M> {
         sda: Option<PeripheralRef<'d, AnyPin>>,
         dma: Option<ChannelAndRequest<'d>>,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
+        let timing = configure_timing(T::frequency().0, &config)?;
+
         unsafe { T::Interrupt::enable() };
 
         let mut this = Self {
@@ -504,12 +591,27 @@ impl<'d, M: Mode> I2c<'d, M> {
             scl,
             sda,
             dma,
+            timing,
+            bus_recovery: config.bus_recovery,
             #[cfg(feature = "time")]
             timeout: config.timeout,
             _phantom: PhantomData,
         };
-        this.init(config);
-        this
+        this.init();
+        Ok(this)
+    }
+
+    /// Reconfigure the driver.
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.timing = configure_timing(self.kernel_clock.0, config)?;
+        self.bus_recovery = config.bus_recovery;
+        #[cfg(feature = "time")]
+        {
+            self.timeout = config.timeout;
+        }
+
+        self.init();
+        Ok(())
     }
 
     fn timeout(&self) -> Timeout {
@@ -529,12 +631,12 @@ impl<'d, M: Mode> I2c<'d, M> {
     }
 
     // init master
-    pub(crate) fn init(&mut self, config: Config) {
+    pub(crate) fn init(&mut self) {
         let r = self.info.regs;
 
         self.reset();
 
-        let timing = configure_timing(self.kernel_clock.0, config.mode).unwrap();
+        let timing = self.timing;
 
         r.tpm().write(|w| w.set_tpm(HPM_I2C_DRV_DEFAULT_TPM as _));
 
@@ -584,12 +686,10 @@ impl<'d, M: Mode> I2c<'d, M> {
             return Err(Error::InvalidArgument);
         }
 
-        let r = self.info.regs;
         let timeout = self.timeout();
+        self.blocking_wait_idle(timeout)?;
 
-        while r.status().read().busbusy() {
-            self.timeout().check()?;
-        }
+        let r = self.info.regs;
 
         // W1C, clear CMPL bit to avoid blocking the transmission
         r.status().write(|w| w.set_cmpl(true));
@@ -605,7 +705,7 @@ impl<'d, M: Mode> I2c<'d, M> {
             w.set_datacnt(reg.len() as _);
         });
 
-        r.addr().modify(|w| w.set_addr(addr as u16));
+        set_address(r, Address::SevenBit(addr))?;
 
         for b in reg {
             r.data().write(|w| w.set_data(*b));
@@ -666,7 +766,7 @@ impl<'d, M: Mode> I2c<'d, M> {
 
     fn blocking_do_operation_timeout(
         &mut self,
-        addr: u8,
+        addr: Address,
         op: &mut Operation<'_>,
         timeout: Timeout,
         frame: FrameOptions,
@@ -681,15 +781,14 @@ impl<'d, M: Mode> I2c<'d, M> {
             return Err(Error::InvalidArgument);
         }
 
-        while r.status().read().busbusy() {
-            timeout.check()?;
-        }
+        // The idle bus is checked by the caller, before the first operation.
+        // The bus is still held by this transaction for a repeated START, or when no START is sent.
 
         // W1C, clear CMPL bit to avoid blocking the transmission
         r.status().write(|w| w.set_cmpl(true));
 
         r.cmd().write(|w| w.set_cmd(vals::Cmd::CLEAR_FIFO));
-        r.addr().write(|w| w.set_addr(addr as u16));
+        set_address(r, addr)?;
         r.ctrl().write(|w| {
             w.set_phase_start(frame.send_start);
             w.set_phase_stop(frame.send_stop);
@@ -706,11 +805,14 @@ impl<'d, M: Mode> I2c<'d, M> {
         r.cmd().write(|w| w.set_cmd(vals::Cmd::DATA_TRANSACTION));
 
         // Before starting to transmit data, judge addrhit to ensure that the slave address exists on the bus.
-        while !r.status().read().addrhit() {
-            timeout.check()?;
-        }
+        // Without an address phase, the operation continues the data of the last one.
+        if frame.send_addr {
+            while !r.status().read().addrhit() {
+                timeout.check()?;
+            }
 
-        r.status().write(|w| w.set_addrhit(true));
+            r.status().write(|w| w.set_addrhit(true));
+        }
 
         // when size is zero, it's probe slave device, so directly return success
         if size == 0 {
@@ -759,7 +861,7 @@ impl<'d, M: Mode> I2c<'d, M> {
     // i2c_master_write
     fn blocking_read_timeout(&mut self, addr: u8, read: &mut [u8], timeout: Timeout) -> Result<(), Error> {
         self.blocking_do_operation_timeout(
-            addr,
+            Address::SevenBit(addr),
             &mut Operation::Read(read),
             timeout,
             FrameOptions {
@@ -779,7 +881,7 @@ impl<'d, M: Mode> I2c<'d, M> {
         send_stop: bool,
     ) -> Result<(), Error> {
         self.blocking_do_operation_timeout(
-            addr,
+            Address::SevenBit(addr),
             &mut Operation::Write(write),
             timeout,
             FrameOptions {
@@ -793,6 +895,7 @@ impl<'d, M: Mode> I2c<'d, M> {
     /// Blocking read.
     pub fn blocking_read(&mut self, addr: u8, read: &mut [u8]) -> Result<(), Error> {
         let timeout = self.timeout();
+        self.blocking_wait_idle(timeout)?;
 
         self.blocking_read_timeout(addr, read, timeout)
     }
@@ -800,13 +903,19 @@ impl<'d, M: Mode> I2c<'d, M> {
     /// Blocking write.
     pub fn blocking_write(&mut self, addr: u8, write: &[u8]) -> Result<(), Error> {
         let timeout = self.timeout();
+        self.blocking_wait_idle(timeout)?;
 
         self.blocking_write_timeout(addr, write, timeout, true)
     }
 
     /// Blocking transaction with operations.
     pub fn blocking_transaction(&mut self, addr: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        self.blocking_transaction_inner(Address::SevenBit(addr), operations)
+    }
+
+    fn blocking_transaction_inner(&mut self, addr: Address, operations: &mut [Operation<'_>]) -> Result<(), Error> {
         let timeout = self.timeout();
+        self.blocking_wait_idle(timeout)?;
 
         for (op, frame) in operation_frames(operations)? {
             self.blocking_do_operation_timeout(addr, op, timeout, frame)?;
@@ -816,44 +925,6 @@ impl<'d, M: Mode> I2c<'d, M> {
     }
 }
 
-// ==========
-// timeout
-
-#[derive(Copy, Clone)]
-struct Timeout {
-    #[cfg(feature = "time")]
-    deadline: embassy_time::Instant,
-}
-
-#[allow(dead_code)]
-impl Timeout {
-    #[inline]
-    fn check(self) -> Result<(), Error> {
-        #[cfg(feature = "time")]
-        if embassy_time::Instant::now() > self.deadline {
-            return Err(Error::Timeout);
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    fn with<R>(self, fut: impl Future<Output = Result<R, Error>>) -> impl Future<Output = Result<R, Error>> {
-        #[cfg(feature = "time")]
-        {
-            use futures_util::FutureExt;
-
-            embassy_futures::select::select(embassy_time::Timer::at(self.deadline), fut).map(|r| match r {
-                embassy_futures::select::Either::First(_) => Err(Error::Timeout),
-                embassy_futures::select::Either::Second(r) => r,
-            })
-        }
-
-        #[cfg(not(feature = "time"))]
-        fut
-    }
-}
-
 // ==========
 // state and info
 
@@ -970,6 +1041,26 @@ impl<'d> embedded_hal_async::i2c::I2c for I2c<'d, Async> {
     }
 }
 
+impl<'d, M: Mode> embedded_hal::i2c::I2c<embedded_hal::i2c::TenBitAddress> for I2c<'d, M> {
+    fn transaction(
+        &mut self,
+        address: u16,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.blocking_transaction_inner(Address::TenBit(address), operations)
+    }
+}
+
+impl<'d> embedded_hal_async::i2c::I2c<embedded_hal_async::i2c::TenBitAddress> for I2c<'d, Async> {
+    async fn transaction(
+        &mut self,
+        address: u16,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.transaction_inner(Address::TenBit(address), operations).await
+    }
+}
+
 // ==========
 // frame options
 
@@ -1040,6 +1131,15 @@ fn operation_frames<'a, 'b: 'a>(
 // ==========
 // helper functions
 
+/// Set the target address of master mode, or the own address of slave mode.
+#[inline]
+fn set_address(r: crate::pac::i2c::I2c, addr: Address) -> Result<(), Error> {
+    let raw = addr.raw()?;
+    r.setup().modify(|w| w.set_addressing(addr.is_ten_bit()));
+    r.addr().write(|w| w.set_addr(raw));
+    Ok(())
+}
+
 #[inline]
 fn get_data_count(r: crate::pac::i2c::I2c) -> u16 {
     let ctrl = r.ctrl().read();
@@ -1054,17 +1154,17 @@ fn period_in_100ps(freq: u32) -> i32 {
     (10000000000_u64 / (freq as u64)) as i32
 }
 
-fn configure_timing(src_clk_in_hz: u32, i2c_mode: I2cMode) -> Option<Timings> {
+fn configure_timing(src_clk_in_hz: u32, config: &Config) -> Result<Timings, ConfigError> {
     let mut timing: Timings = unsafe { core::mem::zeroed() };
-    let setup_time: i32;
+    let mut setup_time: i32;
     let hold_time: i32;
-    let period: i32;
+    let mut period: i32;
     let mut temp1: i32;
     let temp2: i32;
     let temp3: i32;
     let tpclk = period_in_100ps(src_clk_in_hz);
 
-    match i2c_mode {
+    match config.mode {
         /*
          *          |Standard mode | Fast mode | Fast mode plus | Uint
          * ---------+--------------+-----------+----------------+-------
@@ -1099,6 +1199,26 @@ fn configure_timing(src_clk_in_hz: u32, i2c_mode: I2cMode) -> Option<Timings> {
         }
     }
 
+    if let Some(frequency) = config.frequency {
+        if frequency.0 == 0 {
+            return Err(ConfigError::FrequencyTooLow);
+        }
+        let min_period = period;
+        period = period_in_100ps(frequency.0);
+        if period < min_period {
+            return Err(ConfigError::FrequencyTooHigh);
+        }
+    }
+
+    // rise and fall times are part of the SCL period, rise time delays the data setup
+    let rise_time = config.rise_time_ns as i32 * 10;
+    let fall_time = config.fall_time_ns as i32 * 10;
+    period -= rise_time + fall_time;
+    setup_time += rise_time;
+    if period <= 0 {
+        return Err(ConfigError::FrequencyTooHigh);
+    }
+
     /*
      * Spike Suppression | Standard | Fast mode | Fast mode plus | Uint
      *                   | mode     |           |                |
@@ -1153,5 +1273,17 @@ fn configure_timing(src_clk_in_hz: u32, i2c_mode: I2cMode) -> Option<Timings> {
     timing.t_high = (2 * tpclk + (2 + timing.t_sp as i32 + timing.t_sclhi as i32) * tpclk) as u32;
     timing.t_low = (timing.t_high as i32 * timing.t_sclratio as i32) as u32;
 
-    Some(timing)
+    // field widths of the SETUP register
+    if timing.t_sp > 0x7 || timing.t_sudat > 0x1f || timing.t_hddat > 0x1f {
+        return Err(ConfigError::TimingOutOfRange);
+    }
+    if timing.t_sclhi > 0x1ff {
+        return Err(ConfigError::FrequencyTooLow);
+    }
+    // min SCL high and low periods of the mode don't fit in the SCL period
+    if (timing.t_high + timing.t_low) as i32 > period {
+        return Err(ConfigError::FrequencyTooHigh);
+    }
+
+    Ok(timing)
 }
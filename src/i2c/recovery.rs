@@ -0,0 +1,160 @@
+//! I2C bus recovery.
+//!
+//! A slave reset in the middle of a read can keep SDA low, waiting for clocks to shift out the rest of a byte.
+//! The controller then sees a busy bus and can't generate a START. The recovery clocks SCL as GPIO until the slave
+//! releases SDA, up to 9 pulses, then generates a STOP and reinitializes the controller.
+
+use embedded_hal::delay::DelayNs;
+use riscv::delay::McycleDelay;
+
+use super::{Error, I2c};
+use crate::gpio::SealedPin;
+use crate::internal::timeout::Timeout;
+use crate::mode::Mode;
+
+/// Half period of the recovery clock, 100kHz
+const RECOVERY_HALF_PERIOD_US: u32 = 5;
+
+/// Max wait for an idle bus in the blocking API, without the `time` feature
+const IDLE_WAIT_MAX_US: u32 = 100_000;
+
+impl<'d, M: Mode> I2c<'d, M> {
+    /// Recover a bus stuck by a slave holding SDA low.
+    ///
+    /// SCL and SDA are taken as GPIO, SCL is clocked until SDA is released, up to 9 pulses, then a STOP is
+    /// generated and the controller is reinitialized. Returns [`Error::Bus`] if SCL or SDA is still held low.
+    ///
+    /// This is called automatically on a busy bus when [`Config::bus_recovery`](super::Config::bus_recovery) is set.
+    pub fn recover_bus(&mut self) -> Result<(), Error> {
+        // release the lines driven by the controller
+        self.reset();
+
+        let (Some(scl), Some(sda)) = (self.scl.as_ref(), self.sda.as_ref()) else {
+            return Err(Error::InvalidArgument);
+        };
+
+        let scl_func = scl.ioc_pad().func_ctl().read();
+        let scl_pad = scl.ioc_pad().pad_ctl().read();
+        let sda_func = sda.ioc_pad().func_ctl().read();
+        let sda_pad = sda.ioc_pad().pad_ctl().read();
+
+        // GPIO, Open Drain, Pull-up, released
+        for pin in [scl, sda] {
+            pin.set_high();
+            pin.ioc_pad().pad_ctl().modify(|w| {
+                w.set_od(true);
+                w.set_pe(true);
+                w.set_ps(true);
+            });
+            pin.ioc_pad().func_ctl().modify(|w| w.set_alt_select(0));
+            pin.set_as_output();
+        }
+
+        let mut delay = McycleDelay::new(crate::sysctl::clocks().cpu0.0);
+        delay.delay_us(RECOVERY_HALF_PERIOD_US);
+
+        let scl_released = scl.is_high();
+        if scl_released {
+            for _ in 0..9 {
+                if sda.is_high() {
+                    break;
+                }
+                scl.set_low();
+                delay.delay_us(RECOVERY_HALF_PERIOD_US);
+                scl.set_high();
+                delay.delay_us(RECOVERY_HALF_PERIOD_US);
+            }
+
+            // STOP: SDA rising while SCL is high
+            scl.set_low();
+            delay.delay_us(RECOVERY_HALF_PERIOD_US);
+            sda.set_low();
+            delay.delay_us(RECOVERY_HALF_PERIOD_US);
+            scl.set_high();
+            delay.delay_us(RECOVERY_HALF_PERIOD_US);
+            sda.set_high();
+            delay.delay_us(RECOVERY_HALF_PERIOD_US);
+        }
+
+        scl.ioc_pad().pad_ctl().write_value(scl_pad);
+        scl.ioc_pad().func_ctl().write_value(scl_func);
+        sda.ioc_pad().pad_ctl().write_value(sda_pad);
+        sda.ioc_pad().func_ctl().write_value(sda_func);
+        scl.set_as_input();
+        sda.set_as_input();
+
+        if !scl_released {
+            #[cfg(feature = "defmt")]
+            defmt::warn!("SCL is held low, can't recover the bus");
+            return Err(Error::Bus);
+        }
+
+        // I2C_SUPPORT_RESET parts also generate the reset clocks by hardware in `init`, if SDA is still low
+        self.init();
+
+        if self.info.regs.status().read().linesda() {
+            Ok(())
+        } else {
+            Err(Error::Bus)
+        }
+    }
+
+    /// Wait for an idle bus before a transaction.
+    pub(super) fn blocking_wait_idle(&mut self, timeout: Timeout) -> Result<(), Error> {
+        let r = self.info.regs;
+
+        #[cfg(not(feature = "time"))]
+        let mut delay = McycleDelay::new(crate::sysctl::clocks().cpu0.0);
+        #[cfg(not(feature = "time"))]
+        let mut waited_us = 0;
+
+        while r.status().read().busbusy() {
+            if let Err(e) = timeout.check() {
+                return self.recover_or(e.into());
+            }
+
+            #[cfg(not(feature = "time"))]
+            {
+                if waited_us >= IDLE_WAIT_MAX_US {
+                    return self.recover_or(Error::Timeout);
+                }
+                delay.delay_us(1);
+                waited_us += 1;
+            }
+        }
+
+        self.check_sda()
+    }
+
+    /// Wait for an idle bus before a transaction.
+    pub(super) async fn wait_idle(&mut self) -> Result<(), Error> {
+        if self.info.regs.status().read().busbusy() {
+            let timeout = self.timeout();
+            let fut = self.wait_for_stop();
+            if let Err(e) = timeout.with(fut).await {
+                return self.recover_or(e);
+            }
+        }
+
+        self.check_sda()
+    }
+
+    /// SDA low on an idle bus, held by a slave
+    fn check_sda(&mut self) -> Result<(), Error> {
+        if self.info.regs.status().read().linesda() {
+            Ok(())
+        } else {
+            self.recover_or(Error::BusyBusy)
+        }
+    }
+
+    fn recover_or(&mut self, err: Error) -> Result<(), Error> {
+        if self.bus_recovery && err != Error::Arbitration {
+            #[cfg(feature = "defmt")]
+            defmt::warn!("I2C bus is stuck, recovering");
+            self.recover_bus()
+        } else {
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,533 @@
+//! I2C slave (target) mode.
+//!
+//! The slave listens on its own address. Each transaction addressed to it is reported by
+//! [`I2cSlave::listen`], then answered with [`I2cSlave::respond_to_read`] or [`I2cSlave::respond_to_write`].
+//! The bus is held by clock stretching meanwhile.
+//!
+//! A transaction ends with a STOP or a repeated START.
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::Poll;
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+use futures_util::future::poll_fn;
+use hpm_metapac::i2c::vals;
+
+use super::{
+    configure_timing, get_data_count, Address, Config, ConfigError, Error, I2cDma, I2cMode, Info, Instance,
+    InterruptHandler, SclPin, SdaPin, State, HPM_I2C_DRV_DEFAULT_TPM, I2C_SOC_TRANSFER_COUNT_MAX,
+};
+use crate::dma::ChannelAndRequest;
+use crate::gpio::{AnyPin, SealedPin};
+use crate::internal::timeout::Timeout;
+use crate::interrupt;
+use crate::interrupt::typelevel::Interrupt as _;
+use crate::mode::{Async, Blocking, Mode};
+
+/// Byte sent when the master reads more than provided
+const FILLER_BYTE: u8 = 0xFF;
+
+/// I2C slave config
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct SlaveConfig {
+    /// Own address, 7-bit or 10-bit
+    pub address: Address,
+    /// Bus mode, for the setup and hold timings
+    pub mode: I2cMode,
+    /// Timeout of the blocking waits, for the master to address this slave or to end the transaction.
+    #[cfg(feature = "time")]
+    pub timeout: embassy_time::Duration,
+}
+
+impl Default for SlaveConfig {
+    fn default() -> Self {
+        Self {
+            address: Address::SevenBit(0x42),
+            mode: I2cMode::Standard,
+            #[cfg(feature = "time")]
+            timeout: embassy_time::Duration::from_millis(1000),
+        }
+    }
+}
+
+/// Transaction requested by the master.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlaveCommand {
+    /// The master reads, respond with [`I2cSlave::respond_to_read`].
+    Read,
+    /// The master writes, respond with [`I2cSlave::respond_to_write`].
+    Write,
+}
+
+/// I2C slave driver.
+#[allow(unused)]
+pub struct I2cSlave<'d, M: Mode> {
+    info: &'static Info,
+    state: &'static State,
+    scl: Option<PeripheralRef<'d, AnyPin>>,
+    sda: Option<PeripheralRef<'d, AnyPin>>,
+    dma: Option<ChannelAndRequest<'d>>,
+    #[cfg(feature = "time")]
+    timeout: embassy_time::Duration,
+    _phantom: PhantomData<M>,
+}
+
+impl<'d> I2cSlave<'d, Blocking> {
+    /// Create a new blocking I2C slave driver.
+    pub fn new_blocking<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        config: SlaveConfig,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(scl, sda);
+
+        // ALT, Open Drain, Pull-up
+        scl.ioc_pad().func_ctl().write(|w| {
+            w.set_alt_select(scl.alt_num());
+            w.set_loop_back(true);
+        });
+        scl.ioc_pad().pad_ctl().write(|w| {
+            w.set_od(true);
+            w.set_pe(true);
+            w.set_ps(true);
+        });
+        sda.ioc_pad().func_ctl().write(|w| {
+            w.set_alt_select(sda.alt_num());
+            w.set_loop_back(true);
+        });
+        sda.ioc_pad().pad_ctl().write(|w| {
+            w.set_od(true);
+            w.set_pe(true);
+            w.set_ps(true);
+        });
+
+        T::add_resource_group(0);
+        {
+            use crate::sysctl::*;
+            T::set_clock(ClockConfig::new(ClockMux::CLK_24M, 1));
+        }
+
+        Self::new_inner(peri, Some(scl.map_into()), Some(sda.map_into()), None, config)
+    }
+}
+
+impl<'d> I2cSlave<'d, Async> {
+    /// Create a new async I2C slave driver, using DMA for data phases.
+    pub fn new<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        dma: impl Peripheral<P = impl I2cDma<T>> + 'd,
+        config: SlaveConfig,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(scl, sda);
+
+        scl.ioc_pad().func_ctl().write(|w| {
+            w.set_alt_select(scl.alt_num());
+            w.set_loop_back(true);
+        });
+        scl.ioc_pad().pad_ctl().write(|w| {
+            w.set_od(true);
+            w.set_pe(true);
+            w.set_ps(true);
+        });
+        sda.ioc_pad().func_ctl().write(|w| {
+            w.set_alt_select(sda.alt_num());
+            w.set_loop_back(true);
+        });
+        sda.ioc_pad().pad_ctl().write(|w| {
+            w.set_od(true);
+            w.set_pe(true);
+            w.set_ps(true);
+        });
+
+        T::add_resource_group(0);
+        {
+            use crate::sysctl::*;
+            T::set_clock(ClockConfig::new(ClockMux::CLK_24M, 1));
+        }
+
+        Self::new_inner(peri, Some(scl.map_into()), Some(sda.map_into()), new_dma!(dma), config)
+    }
+
+    /// Wait for the master to address this slave.
+    pub async fn listen(&mut self) -> Result<SlaveCommand, Error> {
+        let r = self.info.regs;
+        let s = self.state;
+
+        let _on_drop = OnDrop::new(move || r.int_en().modify(|w| w.set_addrhit(false)));
+
+        poll_fn(|cx| {
+            s.waker.register(cx.waker());
+
+            if r.status().read().addrhit() {
+                return Poll::Ready(());
+            }
+            r.int_en().modify(|w| w.set_addrhit(true));
+
+            Poll::Pending
+        })
+        .await;
+
+        Ok(self.take_command())
+    }
+
+    /// Send `data` to the master, using DMA.
+    ///
+    /// If the master reads more than `data`, `0xFF` is sent.
+    /// Returns the number of bytes of `data` read by the master, the `0xFF` filler bytes are not counted:
+    /// the count is `data.len()` if the master read all of `data`, even if it read filler bytes after it.
+    pub async fn respond_to_read(&mut self, data: &[u8]) -> Result<usize, Error> {
+        if data.is_empty() {
+            self.respond_without_data().await;
+            return Ok(0);
+        }
+        if data.len() > I2C_SOC_TRANSFER_COUNT_MAX {
+            return Err(Error::InvalidArgument);
+        }
+
+        let r = self.info.regs;
+        let s = self.state;
+
+        // with DMA, data count is the number of bytes to transfer, decreased on each byte
+        set_data_count(r, data.len());
+
+        let _on_drop = OnDrop::new(move || finish_transaction(r));
+
+        let ch = self.dma.as_mut().unwrap();
+        let mut transfer = unsafe { ch.write(data, r.data().as_ptr() as *mut u8, Default::default()) };
+
+        r.setup().modify(|w| w.set_dmaen(true));
+
+        let mut filler_sent = false;
+        poll_fn(|cx| {
+            s.waker.register(cx.waker());
+
+            let status = r.status().read();
+            if status.cmpl() {
+                return Poll::Ready(());
+            }
+
+            if Pin::new(&mut transfer).poll(cx).is_ready() {
+                // the master reads more than provided
+                if !status.fifofull() {
+                    filler_sent = true;
+                    r.data().write(|w| w.set_data(FILLER_BYTE));
+                }
+                r.int_en().modify(|w| {
+                    w.set_cmpl(true);
+                    w.set_fifoempty(true);
+                });
+            } else {
+                r.int_en().modify(|w| w.set_cmpl(true));
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        if filler_sent {
+            // the data count doesn't cover the filler bytes, all of `data` was read before them
+            return Ok(data.len());
+        }
+
+        // bytes left in the FIFO are not sent
+        Ok(data.len() - (get_data_count(r) as usize).min(data.len()))
+    }
+
+    /// Receive data from the master into `buffer`, using DMA.
+    ///
+    /// If the master writes more than `buffer`, extra bytes are dropped.
+    /// Returns the number of bytes received in `buffer`.
+    pub async fn respond_to_write(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if buffer.is_empty() {
+            self.respond_without_data().await;
+            return Ok(0);
+        }
+        if buffer.len() > I2C_SOC_TRANSFER_COUNT_MAX {
+            return Err(Error::InvalidArgument);
+        }
+
+        let r = self.info.regs;
+        let s = self.state;
+
+        set_data_count(r, buffer.len());
+
+        let _on_drop = OnDrop::new(move || finish_transaction(r));
+
+        let len = buffer.len();
+        let ch = self.dma.as_mut().unwrap();
+        let mut transfer = unsafe { ch.read(r.data().as_ptr() as *mut u8, buffer, Default::default()) };
+
+        r.setup().modify(|w| w.set_dmaen(true));
+
+        poll_fn(|cx| {
+            s.waker.register(cx.waker());
+
+            let status = r.status().read();
+            if status.cmpl() {
+                return Poll::Ready(());
+            }
+
+            if Pin::new(&mut transfer).poll(cx).is_ready() {
+                // the master writes more than expected, drop them
+                r.setup().modify(|w| w.set_dmaen(false));
+                while !r.status().read().fifoempty() {
+                    let _ = r.data().read();
+                }
+                r.int_en().modify(|w| {
+                    w.set_cmpl(true);
+                    w.set_fifofull(true);
+                });
+            } else {
+                r.int_en().modify(|w| w.set_cmpl(true));
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        // let the DMA move the last bytes out of the FIFO
+        while transfer.is_running() && !r.status().read().fifoempty() {}
+        let remaining = transfer.get_remaining_transfers() as usize;
+        drop(transfer);
+
+        Ok(len - remaining)
+    }
+
+    /// Wait for the end of a transaction without data to exchange:
+    /// filler bytes are sent to a reading master, bytes of a writing master are dropped.
+    async fn respond_without_data(&mut self) {
+        let r = self.info.regs;
+        let s = self.state;
+
+        let _on_drop = OnDrop::new(move || finish_transaction(r));
+
+        poll_fn(|cx| {
+            s.waker.register(cx.waker());
+
+            let status = r.status().read();
+            if status.cmpl() {
+                return Poll::Ready(());
+            }
+
+            if r.ctrl().read().dir() == vals::Dir::MASTER_READ_SLAVE_WRITE {
+                if !status.fifofull() {
+                    r.data().write(|w| w.set_data(FILLER_BYTE));
+                }
+                r.int_en().modify(|w| {
+                    w.set_cmpl(true);
+                    w.set_fifoempty(true);
+                });
+            } else {
+                while !r.status().read().fifoempty() {
+                    let _ = r.data().read();
+                }
+                r.int_en().modify(|w| {
+                    w.set_cmpl(true);
+                    w.set_fifofull(true);
+                });
+            }
+
+            Poll::Pending
+        })
+        .await;
+    }
+}
+
+impl<'d, M: Mode> I2cSlave<'d, M> {
+    fn new_inner<T: Instance>(
+        _peri: impl Peripheral<P = T> + 'd,
+        scl: Option<PeripheralRef<'d, AnyPin>>,
+        sda: Option<PeripheralRef<'d, AnyPin>>,
+        dma: Option<ChannelAndRequest<'d>>,
+        config: SlaveConfig,
+    ) -> Result<Self, ConfigError> {
+        let addr = config.address.raw().map_err(|_| ConfigError::InvalidAddress)?;
+
+        // SCL is driven by the master, only the setup and hold timings are used
+        let timing = configure_timing(
+            T::frequency().0,
+            &Config {
+                mode: config.mode,
+                ..Default::default()
+            },
+        )?;
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        let this = Self {
+            info: T::info(),
+            state: T::state(),
+            scl,
+            sda,
+            dma,
+            #[cfg(feature = "time")]
+            timeout: config.timeout,
+            _phantom: PhantomData,
+        };
+
+        let r = this.info.regs;
+
+        r.ctrl().write(|w| w.0 = 0);
+        r.cmd().write(|w| w.set_cmd(vals::Cmd::RESET));
+        r.setup().modify(|w| w.set_iicen(false));
+
+        r.tpm().write(|w| w.set_tpm(HPM_I2C_DRV_DEFAULT_TPM as _));
+
+        r.setup().write(|w| {
+            w.set_t_sp(timing.t_sp as _);
+            w.set_t_sudat(timing.t_sudat as _);
+            w.set_t_hddat(timing.t_hddat as _);
+            w.set_t_sclradio(timing.t_sclratio - 1 != 0);
+            w.set_t_sclhi(timing.t_sclhi as _);
+            w.set_addressing(config.address.is_ten_bit());
+            w.set_iicen(true);
+            w.set_master(false);
+        });
+        r.addr().write(|w| w.set_addr(addr));
+
+        Ok(this)
+    }
+
+    fn timeout(&self) -> Timeout {
+        Timeout {
+            #[cfg(feature = "time")]
+            deadline: embassy_time::Instant::now() + self.timeout,
+        }
+    }
+
+    /// Wait for the master to address this slave.
+    ///
+    /// Returns [`Error::Timeout`] if not addressed within [`SlaveConfig::timeout`], call again to keep listening.
+    pub fn blocking_listen(&mut self) -> Result<SlaveCommand, Error> {
+        let r = self.info.regs;
+        let timeout = self.timeout();
+
+        while !r.status().read().addrhit() {
+            timeout.check()?;
+        }
+
+        Ok(self.take_command())
+    }
+
+    /// Send `data` to the master.
+    ///
+    /// See [`I2cSlave::respond_to_read`].
+    pub fn blocking_respond_to_read(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let r = self.info.regs;
+        let timeout = self.timeout();
+
+        let mut i = 0;
+        loop {
+            let status = r.status().read();
+            if status.cmpl() {
+                break;
+            }
+            if !status.fifofull() {
+                r.data()
+                    .write(|w| w.set_data(data.get(i).copied().unwrap_or(FILLER_BYTE)));
+                i += 1;
+            }
+            if let Err(e) = timeout.check() {
+                finish_transaction(r);
+                return Err(e.into());
+            }
+        }
+
+        // without DMA, data count is the number of bytes transferred
+        let sent = get_data_count(r) as usize;
+        finish_transaction(r);
+
+        Ok(sent.min(data.len()))
+    }
+
+    /// Receive data from the master into `buffer`.
+    ///
+    /// See [`I2cSlave::respond_to_write`].
+    pub fn blocking_respond_to_write(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let r = self.info.regs;
+        let timeout = self.timeout();
+
+        let mut i = 0;
+        loop {
+            let status = r.status().read();
+            if !status.fifoempty() {
+                let b = r.data().read().data();
+                if let Some(dst) = buffer.get_mut(i) {
+                    *dst = b;
+                    i += 1;
+                }
+            } else if status.cmpl() {
+                break;
+            }
+            if let Err(e) = timeout.check() {
+                finish_transaction(r);
+                return Err(e.into());
+            }
+        }
+
+        finish_transaction(r);
+
+        Ok(i)
+    }
+
+    /// Read and clear the address hit, returns the direction requested by the master.
+    fn take_command(&mut self) -> SlaveCommand {
+        let r = self.info.regs;
+
+        // W1C
+        r.status().write(|w| w.set_addrhit(true));
+
+        // in slave mode, the R/W bit of the address byte
+        if r.ctrl().read().dir() == vals::Dir::MASTER_READ_SLAVE_WRITE {
+            SlaveCommand::Read
+        } else {
+            SlaveCommand::Write
+        }
+    }
+}
+
+impl<'d, M: Mode> Drop for I2cSlave<'d, M> {
+    fn drop(&mut self) {
+        let r = self.info.regs;
+
+        finish_transaction(r);
+        r.int_en().modify(|w| w.set_addrhit(false));
+        r.setup().modify(|w| w.set_iicen(false));
+
+        self.scl.as_ref().map(|x| x.set_as_default());
+        self.sda.as_ref().map(|x| x.set_as_default());
+    }
+}
+
+fn set_data_count(r: crate::pac::i2c::I2c, len: usize) {
+    r.ctrl().modify(|w| {
+        #[cfg(ip_feature_i2c_transfer_count_max_4096)]
+        w.set_datacnt_high((len >> 8) as _);
+        w.set_datacnt(len as _);
+    });
+}
+
+/// Clean up after a transaction, for the next one.
+fn finish_transaction(r: crate::pac::i2c::I2c) {
+    r.int_en().modify(|w| {
+        w.set_cmpl(false);
+        w.set_fifoempty(false);
+        w.set_fifofull(false);
+    });
+    r.setup().modify(|w| w.set_dmaen(false));
+    r.cmd().write(|w| w.set_cmd(vals::Cmd::CLEAR_FIFO));
+    // clear status, W1C, except address hit of the next transaction
+    let mut status = r.status().read();
+    status.set_addrhit(false);
+    r.status().write_value(status);
+}
@@ -0,0 +1,500 @@
+//! SMBus and PMBus, on top of the I2C master driver.
+//!
+//! Supported protocols: quick command, send/receive byte, read/write byte and word, block read/write,
+//! process call and block process call. Packet error checking (PEC) is optional, see [`Smbus::set_pec`].
+//!
+//! The `timeout` of the I2C [`Config`] is the deadline of a whole message, see [`Config::smbus`].
+//! The SMBALERT# signal is handled by [`SmbAlert`].
+
+use embassy_hal_internal::Peripheral;
+use embedded_hal::i2c::Operation;
+
+use super::{Address, Config, Error as I2cError, FrameOptions, I2c, I2cMode};
+use crate::gpio::{Input, Pin, Pull};
+use crate::internal::timeout::Elapsed;
+use crate::mode::{Async, Mode};
+
+/// Max data length of a block transfer, SMBus 3.0
+pub const MAX_BLOCK_LEN: usize = 255;
+
+/// Alert Response Address
+pub const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+
+/// command + byte count + data + PEC
+const MAX_MESSAGE_LEN: usize = 2 + MAX_BLOCK_LEN + 1;
+
+impl Config {
+    /// Config for SMBus, 100kHz with a 35ms timeout, the max `t_TIMEOUT`.
+    ///
+    /// NOTE: The timeout is the deadline of a whole message, not of each clock stretch like `t_TIMEOUT`.
+    /// A 255-byte block read takes about 24ms at 100kHz, increase the timeout if devices stretch the clock
+    /// during long blocks. Without the `time` feature, there's no timeout.
+    pub fn smbus() -> Self {
+        Self {
+            mode: I2cMode::Standard,
+            #[cfg(feature = "time")]
+            timeout: embassy_time::Duration::from_millis(35),
+            ..Default::default()
+        }
+    }
+}
+
+/// SMBus error.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// I2C error
+    I2c(I2cError),
+    /// Packet error checking failed
+    Pec,
+}
+
+impl From<I2cError> for Error {
+    fn from(e: I2cError) -> Self {
+        Error::I2c(e)
+    }
+}
+
+impl From<Elapsed> for Error {
+    fn from(e: Elapsed) -> Self {
+        Error::I2c(e.into())
+    }
+}
+
+/// CRC-8 packet error code, polynomial x^8 + x^2 + x + 1.
+pub fn pec(data: &[u8]) -> u8 {
+    pec_update(0, data)
+}
+
+fn pec_update(mut crc: u8, data: &[u8]) -> u8 {
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// SMBALERT# input, active low.
+pub struct SmbAlert<'d> {
+    pin: Input<'d>,
+}
+
+impl<'d> SmbAlert<'d> {
+    /// Create a new SMBALERT# input, with the internal pull-up.
+    pub fn new(pin: impl Peripheral<P = impl Pin> + 'd) -> Self {
+        Self {
+            pin: Input::new(pin, Pull::Up),
+        }
+    }
+
+    /// Whether a device is asserting SMBALERT#.
+    pub fn is_asserted(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// Wait for SMBALERT# to be asserted.
+    pub async fn wait(&mut self) {
+        self.pin.wait_for_low().await
+    }
+}
+
+/// SMBus master.
+pub struct Smbus<'d, M: Mode> {
+    i2c: I2c<'d, M>,
+    pec: bool,
+}
+
+impl<'d, M: Mode> Smbus<'d, M> {
+    /// Create a new SMBus master on an I2C driver, see [`Config::smbus`].
+    pub fn new(i2c: I2c<'d, M>, pec: bool) -> Self {
+        Self { i2c, pec }
+    }
+
+    /// Enable or disable packet error checking.
+    pub fn set_pec(&mut self, pec: bool) {
+        self.pec = pec;
+    }
+
+    /// Release the I2C driver.
+    pub fn into_inner(self) -> I2c<'d, M> {
+        self.i2c
+    }
+
+    /// Quick command, the R/W bit is the data.
+    pub fn blocking_quick_command(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        if read {
+            self.i2c.blocking_read(addr, &mut [])?;
+        } else {
+            self.i2c.blocking_write(addr, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Send byte, without command code.
+    pub fn blocking_send_byte(&mut self, addr: u8, byte: u8) -> Result<(), Error> {
+        self.blocking_write_message(addr, &[byte], &[])
+    }
+
+    /// Receive byte, without command code.
+    pub fn blocking_receive_byte(&mut self, addr: u8) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.blocking_write_read_message(addr, &[], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Write byte.
+    pub fn blocking_write_byte(&mut self, addr: u8, cmd: u8, byte: u8) -> Result<(), Error> {
+        self.blocking_write_message(addr, &[cmd, byte], &[])
+    }
+
+    /// Read byte.
+    pub fn blocking_read_byte(&mut self, addr: u8, cmd: u8) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.blocking_write_read_message(addr, &[cmd], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Write word, low byte first.
+    pub fn blocking_write_word(&mut self, addr: u8, cmd: u8, word: u16) -> Result<(), Error> {
+        let [lo, hi] = word.to_le_bytes();
+        self.blocking_write_message(addr, &[cmd, lo, hi], &[])
+    }
+
+    /// Read word, low byte first.
+    pub fn blocking_read_word(&mut self, addr: u8, cmd: u8) -> Result<u16, Error> {
+        let mut buf = [0u8; 2];
+        self.blocking_write_read_message(addr, &[cmd], &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Process call, write a word then read a word.
+    pub fn blocking_process_call(&mut self, addr: u8, cmd: u8, word: u16) -> Result<u16, Error> {
+        let [lo, hi] = word.to_le_bytes();
+        let mut buf = [0u8; 2];
+        self.blocking_write_read_message(addr, &[cmd, lo, hi], &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Block write, the byte count is sent before `data`.
+    pub fn blocking_block_write(&mut self, addr: u8, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_BLOCK_LEN {
+            return Err(I2cError::InvalidArgument.into());
+        }
+        self.blocking_write_message(addr, &[cmd, data.len() as u8], data)
+    }
+
+    /// Block read, returns the byte count sent by the device.
+    ///
+    /// Returns [`Error::I2c`] with [`Overrun`](super::Error::Overrun) if the block doesn't fit in `buf`.
+    pub fn blocking_block_read(&mut self, addr: u8, cmd: u8, buf: &mut [u8]) -> Result<usize, Error> {
+        self.blocking_block_read_message(addr, &[cmd], buf)
+    }
+
+    /// Block write, block read process call. Returns the byte count of the read block.
+    pub fn blocking_block_process_call(
+        &mut self,
+        addr: u8,
+        cmd: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        if data.len() > MAX_BLOCK_LEN {
+            return Err(I2cError::InvalidArgument.into());
+        }
+        let mut write = [0u8; MAX_MESSAGE_LEN];
+        write[0] = cmd;
+        write[1] = data.len() as u8;
+        write[2..2 + data.len()].copy_from_slice(data);
+
+        self.blocking_block_read_message(addr, &write[..2 + data.len()], buf)
+    }
+
+    /// Read the address of the device asserting SMBALERT#, from the Alert Response Address.
+    pub fn blocking_alert_response(&mut self) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.i2c.blocking_read(ALERT_RESPONSE_ADDRESS, &mut buf)?;
+        Ok(buf[0] >> 1)
+    }
+
+    fn blocking_write_message(&mut self, addr: u8, header: &[u8], data: &[u8]) -> Result<(), Error> {
+        let mut buf = [0u8; MAX_MESSAGE_LEN];
+        let msg = self.message(addr, header, data, &mut buf);
+        self.i2c.blocking_write(addr, msg)?;
+        Ok(())
+    }
+
+    fn blocking_write_read_message(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), Error> {
+        let mut buf = [0u8; 3];
+        let rbuf = &mut buf[..read.len() + self.pec as usize];
+
+        if write.is_empty() {
+            self.i2c.blocking_read(addr, rbuf)?;
+        } else {
+            self.i2c.blocking_write_read(addr, write, rbuf)?;
+        }
+
+        self.check_pec(addr, write, rbuf)?;
+        read.copy_from_slice(&rbuf[..read.len()]);
+        Ok(())
+    }
+
+    fn blocking_block_read_message(&mut self, addr: u8, write: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        let i2c = &mut self.i2c;
+        let address = Address::SevenBit(addr);
+
+        let timeout = i2c.timeout();
+        i2c.blocking_wait_idle(timeout)?;
+
+        i2c.blocking_do_operation_timeout(address, &mut Operation::Write(write), timeout, FRAME_FIRST)?;
+
+        // the byte count decides the length of the rest of the read
+        let mut count = [0u8; 1];
+        i2c.blocking_do_operation_timeout(address, &mut Operation::Read(&mut count), timeout, FRAME_FIRST)?;
+
+        let mut data = [0u8; MAX_BLOCK_LEN + 1];
+        let n = count[0] as usize + self.pec as usize;
+        if n == 0 {
+            // end the read with a dummy byte, the STOP is sent after a data phase
+            let mut dummy = [0u8; 1];
+            i2c.blocking_do_operation_timeout(address, &mut Operation::Read(&mut dummy), timeout, FRAME_LAST)?;
+            return Err(I2cError::ZeroLengthTransfer.into());
+        }
+        i2c.blocking_do_operation_timeout(address, &mut Operation::Read(&mut data[..n]), timeout, FRAME_LAST)?;
+
+        self.finish_block_read(addr, write, count[0], &data[..n], buf)
+    }
+
+    /// Build a write message, with the PEC if enabled.
+    fn message<'b>(&self, addr: u8, header: &[u8], data: &[u8], buf: &'b mut [u8; MAX_MESSAGE_LEN]) -> &'b [u8] {
+        let mut len = header.len() + data.len();
+        buf[..header.len()].copy_from_slice(header);
+        buf[header.len()..len].copy_from_slice(data);
+
+        if self.pec {
+            buf[len] = pec_update(pec(&[addr << 1]), &buf[..len]);
+            len += 1;
+        }
+
+        &buf[..len]
+    }
+
+    /// Check the PEC of a read, the last byte of `read`.
+    fn check_pec(&self, addr: u8, write: &[u8], read: &[u8]) -> Result<(), Error> {
+        if !self.pec {
+            return Ok(());
+        }
+
+        let (data, code) = read.split_at(read.len() - 1);
+        let mut crc = 0;
+        if !write.is_empty() {
+            crc = pec_update(crc, &[addr << 1]);
+            crc = pec_update(crc, write);
+        }
+        crc = pec_update(crc, &[(addr << 1) | 1]);
+        crc = pec_update(crc, data);
+
+        if crc == code[0] {
+            Ok(())
+        } else {
+            Err(Error::Pec)
+        }
+    }
+
+    fn finish_block_read(
+        &self,
+        addr: u8,
+        write: &[u8],
+        count: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        if self.pec {
+            let mut read = [0u8; MAX_BLOCK_LEN + 2];
+            read[0] = count;
+            read[1..1 + data.len()].copy_from_slice(data);
+            self.check_pec(addr, write, &read[..1 + data.len()])?;
+        }
+
+        let count = count as usize;
+        if count > buf.len() {
+            return Err(I2cError::Overrun.into());
+        }
+        buf[..count].copy_from_slice(&data[..count]);
+
+        Ok(count)
+    }
+}
+
+impl<'d> Smbus<'d, Async> {
+    /// Quick command, the R/W bit is the data.
+    pub async fn quick_command(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        if read {
+            self.i2c.read(addr, &mut []).await?;
+        } else {
+            self.i2c.write(addr, &[]).await?;
+        }
+        Ok(())
+    }
+
+    /// Send byte, without command code.
+    pub async fn send_byte(&mut self, addr: u8, byte: u8) -> Result<(), Error> {
+        self.write_message(addr, &[byte], &[]).await
+    }
+
+    /// Receive byte, without command code.
+    pub async fn receive_byte(&mut self, addr: u8) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.write_read_message(addr, &[], &mut buf).await?;
+        Ok(buf[0])
+    }
+
+    /// Write byte.
+    pub async fn write_byte(&mut self, addr: u8, cmd: u8, byte: u8) -> Result<(), Error> {
+        self.write_message(addr, &[cmd, byte], &[]).await
+    }
+
+    /// Read byte.
+    pub async fn read_byte(&mut self, addr: u8, cmd: u8) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.write_read_message(addr, &[cmd], &mut buf).await?;
+        Ok(buf[0])
+    }
+
+    /// Write word, low byte first.
+    pub async fn write_word(&mut self, addr: u8, cmd: u8, word: u16) -> Result<(), Error> {
+        let [lo, hi] = word.to_le_bytes();
+        self.write_message(addr, &[cmd, lo, hi], &[]).await
+    }
+
+    /// Read word, low byte first.
+    pub async fn read_word(&mut self, addr: u8, cmd: u8) -> Result<u16, Error> {
+        let mut buf = [0u8; 2];
+        self.write_read_message(addr, &[cmd], &mut buf).await?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Process call, write a word then read a word.
+    pub async fn process_call(&mut self, addr: u8, cmd: u8, word: u16) -> Result<u16, Error> {
+        let [lo, hi] = word.to_le_bytes();
+        let mut buf = [0u8; 2];
+        self.write_read_message(addr, &[cmd, lo, hi], &mut buf).await?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Block write, the byte count is sent before `data`.
+    pub async fn block_write(&mut self, addr: u8, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_BLOCK_LEN {
+            return Err(I2cError::InvalidArgument.into());
+        }
+        self.write_message(addr, &[cmd, data.len() as u8], data).await
+    }
+
+    /// Block read, returns the byte count sent by the device.
+    ///
+    /// Returns [`Error::I2c`] with [`Overrun`](super::Error::Overrun) if the block doesn't fit in `buf`.
+    pub async fn block_read(&mut self, addr: u8, cmd: u8, buf: &mut [u8]) -> Result<usize, Error> {
+        self.block_read_message(addr, &[cmd], buf).await
+    }
+
+    /// Block write, block read process call. Returns the byte count of the read block.
+    pub async fn block_process_call(&mut self, addr: u8, cmd: u8, data: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        if data.len() > MAX_BLOCK_LEN {
+            return Err(I2cError::InvalidArgument.into());
+        }
+        let mut write = [0u8; MAX_MESSAGE_LEN];
+        write[0] = cmd;
+        write[1] = data.len() as u8;
+        write[2..2 + data.len()].copy_from_slice(data);
+
+        self.block_read_message(addr, &write[..2 + data.len()], buf).await
+    }
+
+    /// Read the address of the device asserting SMBALERT#, from the Alert Response Address.
+    pub async fn alert_response(&mut self) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.i2c.read(ALERT_RESPONSE_ADDRESS, &mut buf).await?;
+        Ok(buf[0] >> 1)
+    }
+
+    /// Wait for SMBALERT#, then read the address of the device asserting it.
+    pub async fn wait_for_alert(&mut self, alert: &mut SmbAlert<'_>) -> Result<u8, Error> {
+        alert.wait().await;
+        self.alert_response().await
+    }
+
+    async fn write_message(&mut self, addr: u8, header: &[u8], data: &[u8]) -> Result<(), Error> {
+        let mut buf = [0u8; MAX_MESSAGE_LEN];
+        let msg = self.message(addr, header, data, &mut buf);
+        self.i2c.write(addr, msg).await?;
+        Ok(())
+    }
+
+    async fn write_read_message(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), Error> {
+        let mut buf = [0u8; 3];
+        let rbuf = &mut buf[..read.len() + self.pec as usize];
+
+        if write.is_empty() {
+            self.i2c.read(addr, rbuf).await?;
+        } else {
+            self.i2c
+                .transaction(addr, &mut [Operation::Write(write), Operation::Read(rbuf)])
+                .await?;
+        }
+
+        self.check_pec(addr, write, rbuf)?;
+        read.copy_from_slice(&rbuf[..read.len()]);
+        Ok(())
+    }
+
+    async fn block_read_message(&mut self, addr: u8, write: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        let pec = self.pec;
+        let i2c = &mut self.i2c;
+        let address = Address::SevenBit(addr);
+
+        let timeout = i2c.timeout();
+        i2c.wait_idle().await?;
+
+        let fut = async {
+            i2c.do_operation_inner(address, &mut Operation::Write(write), FRAME_FIRST)
+                .await?;
+
+            // the byte count decides the length of the rest of the read
+            let mut count = [0u8; 1];
+            i2c.do_operation_inner(address, &mut Operation::Read(&mut count), FRAME_FIRST)
+                .await?;
+
+            let mut data = [0u8; MAX_BLOCK_LEN + 1];
+            let n = count[0] as usize + pec as usize;
+            if n == 0 {
+                // end the read with a dummy byte, the STOP is sent after a data phase
+                let mut dummy = [0u8; 1];
+                i2c.do_operation_inner(address, &mut Operation::Read(&mut dummy), FRAME_LAST)
+                    .await?;
+                return Err(I2cError::ZeroLengthTransfer.into());
+            }
+            i2c.do_operation_inner(address, &mut Operation::Read(&mut data[..n]), FRAME_LAST)
+                .await?;
+
+            Ok::<_, Error>((count[0], data, n))
+        };
+        let (count, data, n) = timeout.with(fut).await?;
+
+        self.finish_block_read(addr, write, count, &data[..n], buf)
+    }
+}
+
+/// Start of a message, with address
+const FRAME_FIRST: FrameOptions = FrameOptions {
+    send_start: true,
+    send_stop: false,
+    send_addr: true,
+};
+
+/// Rest of the read, then STOP
+const FRAME_LAST: FrameOptions = FrameOptions {
+    send_start: false,
+    send_stop: true,
+    send_addr: false,
+};
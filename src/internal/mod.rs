@@ -1,4 +1,5 @@
 pub mod interrupt;
+pub(crate) mod timeout;
 
 // used by GPIO interrupt handlers, and DMA controller
 pub(crate) struct BitIter(pub u32);
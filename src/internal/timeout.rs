@@ -0,0 +1,47 @@
+//! Timeout of driver operations, only checked with the `time` feature.
+
+use core::future::Future;
+
+/// Deadline of an operation
+#[derive(Copy, Clone)]
+pub(crate) struct Timeout {
+    #[cfg(feature = "time")]
+    pub deadline: embassy_time::Instant,
+}
+
+/// The deadline has passed, converted into the timeout error of each driver
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Elapsed;
+
+#[allow(dead_code)]
+impl Timeout {
+    #[inline]
+    pub fn check(self) -> Result<(), Elapsed> {
+        #[cfg(feature = "time")]
+        if embassy_time::Instant::now() > self.deadline {
+            return Err(Elapsed);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn with<R, E: From<Elapsed>>(
+        self,
+        fut: impl Future<Output = Result<R, E>>,
+    ) -> impl Future<Output = Result<R, E>> {
+        #[cfg(feature = "time")]
+        {
+            use futures_util::FutureExt;
+
+            embassy_futures::select::select(embassy_time::Timer::at(self.deadline), fut).map(|r| match r {
+                embassy_futures::select::Either::First(_) => Err(Elapsed.into()),
+                embassy_futures::select::Either::Second(r) => r,
+            })
+        }
+
+        #[cfg(not(feature = "time"))]
+        fut
+    }
+}
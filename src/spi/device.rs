@@ -0,0 +1,266 @@
+//! SPI devices using the hardware chip select of the controller.
+//!
+//! The controller asserts CS for the duration of one hardware transaction, with the `cs2sclk` and `csht`
+//! timings of the device. A hardware transaction is an optional command byte followed by one data phase,
+//! so the devices don't implement `SpiDevice`, which allows any sequence of operations and delays.
+//! Each method of [`HwCsDevice`] and [`AsyncHwCsDevice`] runs one hardware transaction:
+//! - `write`, `read`, `transfer` with equal lengths, `transfer_in_place`
+//! - `write_read`, write then read
+//!
+//! with an optional `cmd` byte sent as the command phase before the data.
+//!
+//! Devices on the same bus share the [`Spi`] driver through a `RefCell` or an async `Mutex`.
+//! Selecting between devices needs SPI_CS_SELECT (v53, v68), other parts have a single hardware CS.
+
+use core::cell::RefCell;
+
+use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+use super::{CsIndexPin, CsPin, Error, Instance, Spi, Timings, TransMode, TransferConfig};
+use crate::gpio::AnyPin;
+use crate::mode::{Async, Mode as PeriMode};
+
+/// Blocking SPI device, using the hardware chip select.
+pub struct HwCsDevice<'a, 'd, M: PeriMode> {
+    bus: &'a RefCell<Spi<'d, M>>,
+    _cs: PeripheralRef<'a, AnyPin>,
+    cs_index: u8,
+    timings: Timings,
+}
+
+impl<'a, 'd, M: PeriMode> HwCsDevice<'a, 'd, M> {
+    /// Create a new device on a shared bus.
+    pub fn new<T: Instance>(
+        bus: &'a RefCell<Spi<'d, M>>,
+        cs: impl Peripheral<P = impl CsPin<T> + CsIndexPin<T>> + 'a,
+        timings: Timings,
+    ) -> Self {
+        into_ref!(cs);
+
+        cs.set_as_alt(cs.alt_num());
+        let cs_index = cs.cs_index();
+
+        Self {
+            bus,
+            _cs: cs.map_into(),
+            cs_index,
+            timings,
+        }
+    }
+}
+
+/// Async SPI device, using the hardware chip select.
+pub struct AsyncHwCsDevice<'a, 'd, RM: RawMutex> {
+    bus: &'a Mutex<RM, Spi<'d, Async>>,
+    _cs: PeripheralRef<'a, AnyPin>,
+    cs_index: u8,
+    timings: Timings,
+}
+
+impl<'a, 'd, RM: RawMutex> AsyncHwCsDevice<'a, 'd, RM> {
+    /// Create a new device on a shared bus.
+    pub fn new<T: Instance>(
+        bus: &'a Mutex<RM, Spi<'d, Async>>,
+        cs: impl Peripheral<P = impl CsPin<T> + CsIndexPin<T>> + 'a,
+        timings: Timings,
+    ) -> Self {
+        into_ref!(cs);
+
+        cs.set_as_alt(cs.alt_num());
+        let cs_index = cs.cs_index();
+
+        Self {
+            bus,
+            _cs: cs.map_into(),
+            cs_index,
+            timings,
+        }
+    }
+}
+
+impl<'d, M: PeriMode> Spi<'d, M> {
+    /// Select the hardware CS and its timings for the next transactions.
+    fn select_hw_cs(&mut self, cs_index: u8, timings: &Timings) {
+        let r = self.info.regs;
+
+        r.timing().modify(|w| {
+            w.set_cs2sclk(timings.cs2sclk.into());
+            w.set_csht(timings.csht.into());
+        });
+
+        #[cfg(ip_feature_spi_cs_select)]
+        r.ctrl().modify(|w| w.set_cs_en(cs_index));
+        #[cfg(not(ip_feature_spi_cs_select))]
+        let _ = cs_index;
+    }
+}
+
+/// Data phase of a hardware transaction
+enum DataPhase<'o> {
+    None,
+    Write(&'o [u8]),
+    Read(&'o mut [u8]),
+    Transfer(&'o mut [u8], &'o [u8]),
+    TransferInPlace(&'o mut [u8]),
+    WriteRead(&'o [u8], &'o mut [u8]),
+}
+
+impl DataPhase<'_> {
+    /// Empty buffers have no data phase
+    fn normalize(self) -> Self {
+        match self {
+            DataPhase::Write(w) if w.is_empty() => DataPhase::None,
+            DataPhase::Read(r) if r.is_empty() => DataPhase::None,
+            DataPhase::Transfer(r, _) if r.is_empty() => DataPhase::None,
+            DataPhase::TransferInPlace(b) if b.is_empty() => DataPhase::None,
+            DataPhase::WriteRead(w, r) if w.is_empty() && r.is_empty() => DataPhase::None,
+            DataPhase::WriteRead(w, r) if r.is_empty() => DataPhase::Write(w),
+            DataPhase::WriteRead(w, r) if w.is_empty() => DataPhase::Read(r),
+            data => data,
+        }
+    }
+}
+
+impl<'a, 'd, M: PeriMode> HwCsDevice<'a, 'd, M> {
+    /// Send the optional `cmd`, then write `data`, in one CS assertion.
+    pub fn blocking_write(&mut self, cmd: Option<u8>, data: &[u8]) -> Result<(), Error> {
+        self.blocking_run(cmd, DataPhase::Write(data))
+    }
+
+    /// Send the optional `cmd`, then read into `data`, in one CS assertion.
+    pub fn blocking_read(&mut self, cmd: Option<u8>, data: &mut [u8]) -> Result<(), Error> {
+        self.blocking_run(cmd, DataPhase::Read(data))
+    }
+
+    /// Send the optional `cmd`, then write `write` and read into `read` at the same time, in one CS assertion.
+    ///
+    /// `read` and `write` must have the same length, [`Error::InvalidArgument`] otherwise.
+    pub fn blocking_transfer(&mut self, cmd: Option<u8>, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        if read.len() != write.len() {
+            return Err(Error::InvalidArgument);
+        }
+        self.blocking_run(cmd, DataPhase::Transfer(read, write))
+    }
+
+    /// Send the optional `cmd`, then write `data` and read into it at the same time, in one CS assertion.
+    pub fn blocking_transfer_in_place(&mut self, cmd: Option<u8>, data: &mut [u8]) -> Result<(), Error> {
+        self.blocking_run(cmd, DataPhase::TransferInPlace(data))
+    }
+
+    /// Send the optional `cmd`, write `write`, then read into `read`, in one CS assertion.
+    pub fn blocking_write_read(&mut self, cmd: Option<u8>, write: &[u8], read: &mut [u8]) -> Result<(), Error> {
+        self.blocking_run(cmd, DataPhase::WriteRead(write, read))
+    }
+
+    fn blocking_run(&mut self, cmd: Option<u8>, data: DataPhase<'_>) -> Result<(), Error> {
+        let mut bus = self.bus.borrow_mut();
+        bus.select_hw_cs(self.cs_index, &self.timings);
+
+        let mut config = TransferConfig {
+            cmd,
+            ..Default::default()
+        };
+
+        match data.normalize() {
+            DataPhase::None if cmd.is_none() => return Ok(()),
+            DataPhase::None => {
+                config.transfer_mode = TransMode::NO_DATA;
+                bus.configure_transfer(0, 0, &config)?;
+            }
+            DataPhase::Write(w) => {
+                config.transfer_mode = TransMode::WRITE_ONLY;
+                bus.blocking_transfer(&mut [], w, &config)?;
+            }
+            DataPhase::Read(r) => {
+                config.transfer_mode = TransMode::READ_ONLY;
+                bus.blocking_transfer(r, &[], &config)?;
+            }
+            DataPhase::Transfer(r, w) => {
+                config.transfer_mode = TransMode::WRITE_READ_TOGETHER;
+                bus.blocking_transfer(r, w, &config)?;
+            }
+            DataPhase::TransferInPlace(b) => {
+                config.transfer_mode = TransMode::WRITE_READ_TOGETHER;
+                bus.blocking_transfer_inplace(b, &config)?;
+            }
+            DataPhase::WriteRead(w, r) => {
+                config.transfer_mode = TransMode::WRITE_READ;
+                bus.blocking_transfer(r, w, &config)?;
+            }
+        }
+
+        // CS is deasserted by hardware at the end of the transaction
+        bus.blocking_flush();
+
+        Ok(())
+    }
+}
+
+impl<'a, 'd, RM: RawMutex> AsyncHwCsDevice<'a, 'd, RM> {
+    /// Send the optional `cmd`, then write `data`, in one CS assertion.
+    pub async fn write(&mut self, cmd: Option<u8>, data: &[u8]) -> Result<(), Error> {
+        self.run(cmd, DataPhase::Write(data)).await
+    }
+
+    /// Send the optional `cmd`, then read into `data`, in one CS assertion.
+    pub async fn read(&mut self, cmd: Option<u8>, data: &mut [u8]) -> Result<(), Error> {
+        self.run(cmd, DataPhase::Read(data)).await
+    }
+
+    /// Send the optional `cmd`, then write `write` and read into `read` at the same time, in one CS assertion.
+    ///
+    /// `read` and `write` must have the same length, [`Error::InvalidArgument`] otherwise.
+    pub async fn transfer(&mut self, cmd: Option<u8>, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        if read.len() != write.len() {
+            return Err(Error::InvalidArgument);
+        }
+        self.run(cmd, DataPhase::Transfer(read, write)).await
+    }
+
+    /// Send the optional `cmd`, then write `data` and read into it at the same time, in one CS assertion.
+    pub async fn transfer_in_place(&mut self, cmd: Option<u8>, data: &mut [u8]) -> Result<(), Error> {
+        self.run(cmd, DataPhase::TransferInPlace(data)).await
+    }
+
+    /// Send the optional `cmd`, write `write`, then read into `read`, in one CS assertion.
+    pub async fn write_read(&mut self, cmd: Option<u8>, write: &[u8], read: &mut [u8]) -> Result<(), Error> {
+        self.run(cmd, DataPhase::WriteRead(write, read)).await
+    }
+
+    async fn run(&mut self, cmd: Option<u8>, data: DataPhase<'_>) -> Result<(), Error> {
+        let mut bus = self.bus.lock().await;
+        bus.select_hw_cs(self.cs_index, &self.timings);
+
+        let mut config = TransferConfig {
+            cmd,
+            ..Default::default()
+        };
+
+        match data.normalize() {
+            DataPhase::None if cmd.is_none() => Ok(()),
+            DataPhase::None => bus.command(&config).await,
+            DataPhase::Write(w) => {
+                config.transfer_mode = TransMode::WRITE_ONLY;
+                bus.write_with_config(w, &config).await
+            }
+            DataPhase::Read(r) => {
+                config.transfer_mode = TransMode::READ_ONLY;
+                bus.read_with_config(r, &config).await
+            }
+            DataPhase::Transfer(r, w) => {
+                config.transfer_mode = TransMode::WRITE_READ_TOGETHER;
+                bus.transfer(r, w, &config).await
+            }
+            DataPhase::TransferInPlace(b) => {
+                config.transfer_mode = TransMode::WRITE_READ_TOGETHER;
+                bus.transfer_in_place(b, &config).await
+            }
+            DataPhase::WriteRead(w, r) => {
+                config.transfer_mode = TransMode::WRITE_READ;
+                bus.transfer(r, w, &config).await
+            }
+        }
+    }
+}
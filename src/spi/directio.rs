@@ -0,0 +1,146 @@
+//! SPI direct IO mode.
+//!
+//! In direct IO mode, the SPI pins are driven by software through the controller, without reassigning
+//! the pins to GPIO. Useful for non-standard waveforms, e.g. SD card init clocks with CS high,
+//! wake-up sequences, or bit-level bus recovery.
+
+use embedded_hal::delay::DelayNs;
+use riscv::delay::McycleDelay;
+
+use super::{Error, Spi};
+use crate::mode::Mode as PeriMode;
+use crate::pac::spi::regs::Directio;
+use crate::time::Hertz;
+
+/// SPI signal, in direct IO mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DirectIoPin {
+    /// Chip select
+    Cs,
+    /// Serial clock
+    Sclk,
+    /// MOSI, D0 in dual/quad mode
+    Mosi,
+    /// MISO, D1 in dual/quad mode
+    Miso,
+    /// D2 in quad mode
+    Wp,
+    /// D3 in quad mode
+    Hold,
+}
+
+impl<'d, M: PeriMode> Spi<'d, M> {
+    /// Enter direct IO mode.
+    ///
+    /// CS and MOSI are driven high, SCLK is driven to its idle level, other pins are released.
+    /// Transfers must not be used until [`exit_direct_io`](Self::exit_direct_io) is called.
+    pub fn enter_direct_io(&mut self) {
+        let r = self.info.regs;
+
+        self.blocking_flush();
+
+        // polarity is stored in the CPHA field, see `Spi::enable_and_configure`
+        let sclk_idle = r.trans_fmt().read().cpha();
+
+        r.directio().write(|w| {
+            w.set_cs_o(true);
+            w.set_cs_oe(true);
+            w.set_sclk_o(sclk_idle);
+            w.set_sclk_oe(true);
+            w.set_mosi_o(true);
+            w.set_mosi_oe(true);
+            w.set_directio_en(true);
+        });
+    }
+
+    /// Exit direct IO mode, back to normal transfers. The configuration is kept.
+    pub fn exit_direct_io(&mut self) {
+        self.info.regs.directio().write(|_| {});
+    }
+
+    /// Whether direct IO mode is enabled.
+    pub fn is_direct_io(&self) -> bool {
+        self.info.regs.directio().read().directio_en()
+    }
+
+    /// Drive a pin to the given level, in direct IO mode.
+    pub fn set_direct_io_level(&mut self, pin: DirectIoPin, high: bool) {
+        self.info.regs.directio().modify(|w| set_output(w, pin, Some(high)));
+    }
+
+    /// Stop driving a pin, in direct IO mode. Its level can be read by [`direct_io_level`](Self::direct_io_level).
+    pub fn release_direct_io(&mut self, pin: DirectIoPin) {
+        self.info.regs.directio().modify(|w| set_output(w, pin, None));
+    }
+
+    /// Level of a pin, in direct IO mode.
+    pub fn direct_io_level(&self, pin: DirectIoPin) -> bool {
+        let r = self.info.regs.directio().read();
+        match pin {
+            DirectIoPin::Cs => r.cs_i(),
+            DirectIoPin::Sclk => r.sclk_i(),
+            DirectIoPin::Mosi => r.mosi_i(),
+            DirectIoPin::Miso => r.miso_i(),
+            DirectIoPin::Wp => r.wp_i(),
+            DirectIoPin::Hold => r.hold_i(),
+        }
+    }
+
+    /// Generate `cycles` SCLK clocks at about `frequency`, in direct IO mode.
+    ///
+    /// Other pins are kept at their current levels, e.g. CS and MOSI high for SD card init.
+    /// Returns [`Error::InvalidArgument`] if `frequency` is zero.
+    pub fn blocking_direct_io_clocks(&mut self, cycles: usize, frequency: Hertz) -> Result<(), Error> {
+        if frequency.0 == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let r = self.info.regs;
+
+        let idle = r.directio().read().sclk_o();
+        let half_period_ns = 500_000_000 / frequency.0;
+        let mut delay = McycleDelay::new(crate::sysctl::clocks().cpu0.0);
+
+        for _ in 0..cycles {
+            r.directio().modify(|w| w.set_sclk_o(!idle));
+            delay.delay_ns(half_period_ns);
+            r.directio().modify(|w| w.set_sclk_o(idle));
+            delay.delay_ns(half_period_ns);
+        }
+
+        Ok(())
+    }
+}
+
+/// Set output level and output enable of a pin, `None` to release it.
+fn set_output(w: &mut Directio, pin: DirectIoPin, level: Option<bool>) {
+    let out = level.unwrap_or(false);
+    let oe = level.is_some();
+    match pin {
+        DirectIoPin::Cs => {
+            w.set_cs_o(out);
+            w.set_cs_oe(oe);
+        }
+        DirectIoPin::Sclk => {
+            w.set_sclk_o(out);
+            w.set_sclk_oe(oe);
+        }
+        DirectIoPin::Mosi => {
+            w.set_mosi_o(out);
+            w.set_mosi_oe(oe);
+        }
+        DirectIoPin::Miso => {
+            w.set_miso_o(out);
+            w.set_miso_oe(oe);
+        }
+        DirectIoPin::Wp => {
+            w.set_wp_o(out);
+            w.set_wp_oe(oe);
+        }
+        DirectIoPin::Hold => {
+            w.set_hold_o(out);
+            w.set_hold_oe(oe);
+        }
+    }
+}
@@ -10,18 +10,32 @@ use core::ptr;
 
 use embassy_futures::join::join;
 use embassy_futures::yield_now;
+use embassy_hal_internal::drop::OnDrop;
 use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 // re-export
 pub use embedded_hal::spi::{Mode, MODE_0, MODE_1, MODE_2, MODE_3};
 
 use self::consts::*;
-use crate::dma::{self, word, ChannelAndRequest};
+use crate::dma::{self, ChannelAndRequest};
+// re-export, word types for frames of 1 to 32 bits
+pub use crate::dma::word;
 use crate::gpio::AnyPin;
+use crate::internal::timeout::Elapsed;
+use crate::interrupt;
 use crate::mode::{Async, Blocking, Mode as PeriMode};
 pub use crate::pac::spi::vals::{AddrLen, AddrPhaseFormat, DataPhaseFormat, TransMode};
 use crate::time::Hertz;
 
+mod device;
+#[cfg(ip_feature_spi_support_directio)]
+mod directio;
+mod slave;
+pub use device::*;
+#[cfg(ip_feature_spi_support_directio)]
+pub use directio::DirectIoPin;
+pub use slave::*;
+
 #[cfg(any(hpm53, hpm68, hpm6e))]
 mod consts {
     pub const TRANSFER_COUNT_MAX: usize = 0xFFFFFFFF;
@@ -202,6 +216,37 @@ pub enum Error {
     BufferTooLong,
     /// FIFO FULL
     FifoFull,
+    /// Slave mode, the master wrote more data than expected
+    Overrun,
+    /// Slave mode, the master read more data than provided
+    Underrun,
+}
+
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        Error::Timeout
+    }
+}
+
+// - MARK: Interrupt handler
+
+/// SPI interrupt handler, used by the slave mode driver.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let r = T::info().regs;
+
+        // flags are cleared by the driver
+        r.intr_en().modify(|w| {
+            w.set_endinten(false);
+            w.set_slvcmden(false);
+        });
+
+        T::state().waker.wake();
+    }
 }
 
 // - MARK: SPI driver
@@ -221,6 +266,7 @@ pub struct Spi<'d, M: PeriMode> {
     rx_dma: Option<ChannelAndRequest<'d>>,
     _phantom: PhantomData<M>,
     current_word_size: word_impl::Config,
+    half_duplex: bool,
 }
 
 impl<'d> Spi<'d, Blocking> {
@@ -559,17 +605,83 @@ impl<'d> Spi<'d, Async> {
         )
     }
 
+    /// Create a new async quad SPI driver, for QSPI flash or PSRAM.
+    ///
+    /// Dual and quad phases are selected per transfer, see [`TransferConfig`].
+    pub fn new_quad<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T> + CsIndexPin<T>> + 'd,
+        sclk: impl Peripheral<P = impl SclkPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(cs, sclk, mosi, miso, d2, d3);
+
+        T::add_resource_group(0);
+
+        cs.set_as_alt(cs.alt_num());
+        mosi.set_as_alt(mosi.alt_num());
+        miso.set_as_alt(miso.alt_num());
+        sclk.ioc_pad().func_ctl().modify(|w| {
+            w.set_alt_select(sclk.alt_num());
+            w.set_loop_back(true);
+        });
+        d2.set_as_alt(d2.alt_num());
+        d3.set_as_alt(d3.alt_num());
+
+        #[cfg(ip_feature_spi_cs_select)]
+        {
+            let cs_index = cs.cs_index();
+            T::info().regs.ctrl().modify(|w| w.set_cs_en(cs_index));
+        }
+
+        Self::new_inner(
+            peri,
+            Some(sclk.map_into()),
+            Some(mosi.map_into()),
+            Some(miso.map_into()),
+            Some(d2.map_into()),
+            Some(d3.map_into()),
+            new_dma!(tx_dma),
+            new_dma!(rx_dma),
+            config,
+        )
+    }
+
     /// SPI write, using DMA.
     pub async fn write<W: Word>(&mut self, data: &[W]) -> Result<(), Error> {
+        self.write_with_config(data, &TransferConfig::default()).await
+    }
+
+    pub async fn read<W: Word>(&mut self, data: &mut [W]) -> Result<(), Error> {
+        let mut config = TransferConfig::default();
+        config.transfer_mode = TransMode::READ_ONLY;
+        config.dummy_cnt = data.len() as u8;
+        self.read_with_config(data, &config).await
+    }
+
+    /// SPI write with command, address and dummy phases, using DMA.
+    ///
+    /// `config.transfer_mode` must be `WRITE_ONLY` or `DUMMY_WRITE`.
+    /// Dual and quad phases are set by `config.addr_phase` and `config.data_phase`.
+    pub async fn write_with_config<W: Word>(&mut self, data: &[W], config: &TransferConfig) -> Result<(), Error> {
         if data.is_empty() {
             return Ok(());
         }
+        if !matches!(config.transfer_mode, TransMode::WRITE_ONLY | TransMode::DUMMY_WRITE) {
+            return Err(Error::InvalidArgument);
+        }
 
         let r = self.info.regs;
 
         self.set_word_size(W::CONFIG);
 
-        self.configure_transfer(data.len(), 0, &TransferConfig::default())?;
+        self.configure_transfer(data.len(), 0, config)?;
 
         r.ctrl().modify(|w| w.set_txdmaen(true));
 
@@ -591,18 +703,22 @@ impl<'d> Spi<'d, Async> {
         Ok(())
     }
 
-    pub async fn read<W: Word>(&mut self, data: &mut [W]) -> Result<(), Error> {
+    /// SPI read with command, address and dummy phases, using DMA.
+    ///
+    /// `config.transfer_mode` must be `READ_ONLY` or `DUMMY_READ`.
+    /// Dual and quad phases are set by `config.addr_phase` and `config.data_phase`.
+    pub async fn read_with_config<W: Word>(&mut self, data: &mut [W], config: &TransferConfig) -> Result<(), Error> {
         if data.is_empty() {
             return Ok(());
         }
+        if !matches!(config.transfer_mode, TransMode::READ_ONLY | TransMode::DUMMY_READ) {
+            return Err(Error::InvalidArgument);
+        }
 
         let r = self.info.regs;
 
         self.set_word_size(W::CONFIG);
-        let mut config = TransferConfig::default();
-        config.transfer_mode = TransMode::READ_ONLY;
-        config.dummy_cnt = data.len() as u8;
-        self.configure_transfer(0, data.len(), &config)?;
+        self.configure_transfer(0, data.len(), config)?;
 
         let rx_src = r.data().as_ptr() as *mut W;
         let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read(rx_src, data, Default::default()) };
@@ -616,14 +732,109 @@ impl<'d> Spi<'d, Async> {
         Ok(())
     }
 
+    /// SPI write of bytes, packed into 32-bit words with datamerge, using DMA.
+    ///
+    /// 4 bytes are moved per DMA transfer and per FIFO entry, which is much faster than [`write`](Self::write)
+    /// for large buffers, e.g. display frames. Bytes are sent in memory order.
+    ///
+    /// `data` must be 4-byte aligned, with a length multiple of 4, [`Error::InvalidArgument`] otherwise.
+    /// Chunks over the transfer count limit are sent in separate transactions.
+    pub async fn datamerge_write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let (head, words, tail) = unsafe { data.align_to::<u32>() };
+        if !head.is_empty() || !tail.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        for chunk in words.chunks(TRANSFER_COUNT_MAX / 4) {
+            self.datamerge_write_words(chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn datamerge_write_words(&mut self, words: &[u32]) -> Result<(), Error> {
+        let r = self.info.regs;
+
+        // datamerge works with 8-bit data length only
+        self.set_word_size(<u8 as SealedWord>::CONFIG);
+        r.trans_fmt().modify(|w| w.set_datamerge(true));
+        let _on_drop = OnDrop::new(move || {
+            r.ctrl().modify(|w| w.set_txdmaen(false));
+            r.trans_fmt().modify(|w| w.set_datamerge(false));
+        });
+
+        self.configure_transfer(words.len() * 4, 0, &TransferConfig::default())?;
+
+        r.ctrl().modify(|w| w.set_txdmaen(true));
+
+        // LSB of each word is sent first, so bytes are sent in memory order
+        let tx_dst = r.data().as_ptr() as *mut u32;
+        let mut opts = dma::TransferOptions::default();
+        opts.burst = dma::Burst::from_size(FIFO_SIZE / 2);
+        let tx_f = unsafe { self.tx_dma.as_mut().unwrap().write(words, tx_dst, opts) };
+
+        tx_f.await;
+
+        // See `write`
+        while r.status().read().spiactive() {
+            yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    /// Write RGB565 pixels, using DMA.
+    ///
+    /// With `swap_bytes`, the high byte of each pixel is sent first, as most display controllers expect.
+    /// This uses 16-bit frames, so `Config::bit_order` must be MSB first.
+    /// Without `swap_bytes`, pixels are sent in memory order, low byte first, for frame buffers
+    /// already stored in the display byte order. This uses the faster [`datamerge_write`](Self::datamerge_write)
+    /// if `pixels` is 4-byte aligned with an even length, and 8-bit frames otherwise.
+    pub async fn write_pixels(&mut self, pixels: &[u16], swap_bytes: bool) -> Result<(), Error> {
+        if swap_bytes {
+            for chunk in pixels.chunks(TRANSFER_COUNT_MAX) {
+                self.write(chunk).await?;
+            }
+            return Ok(());
+        }
+
+        let bytes = unsafe { core::slice::from_raw_parts(pixels.as_ptr() as *const u8, pixels.len() * 2) };
+        if bytes.as_ptr() as usize % 4 == 0 && bytes.len() % 4 == 0 {
+            return self.datamerge_write(bytes).await;
+        }
+        for chunk in bytes.chunks(TRANSFER_COUNT_MAX) {
+            self.write(chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Send command and address phases only, without data phase.
+    ///
+    /// `config.transfer_mode` is ignored, `NO_DATA` is used.
+    pub async fn command(&mut self, config: &TransferConfig) -> Result<(), Error> {
+        let r = self.info.regs;
+
+        let mut config = *config;
+        config.transfer_mode = TransMode::NO_DATA;
+        self.configure_transfer(0, 0, &config)?;
+
+        while r.status().read().spiactive() {
+            yield_now().await;
+        }
+
+        Ok(())
+    }
+
     async fn transfer_inner<W: Word>(
         &mut self,
         read: *mut [W],
         write: *const [W],
         config: &TransferConfig,
     ) -> Result<(), Error> {
-        // in dma mode,
-        assert_eq!(read.len(), write.len());
+        // in dma mode, both directions run at the same time
+        if config.transfer_mode == TransMode::WRITE_READ_TOGETHER {
+            assert_eq!(read.len(), write.len());
+        }
 
         let r = self.info.regs;
 
@@ -700,6 +911,7 @@ impl<'d, M: PeriMode> Spi<'d, M> {
             tx_dma,
             rx_dma,
             current_word_size: <u8 as SealedWord>::CONFIG,
+            half_duplex: config.half_duplex,
             _phantom: PhantomData,
         };
 
@@ -752,8 +964,7 @@ impl<'d, M: PeriMode> Spi<'d, M> {
             // addrlen is set in transfer config, not here
             w.set_addrlen(AddrLen::_8BIT);
             // Use 8bit data length by default
-            // TODO: Use 32bit data len + datamerge, improve the performance
-            // 32 bit data length only works when the data is 32bit aligned
+            // datamerge is enabled per transfer, see `datamerge_write`
             w.set_datalen(<u8 as SealedWord>::CONFIG);
             w.set_datamerge(false);
             if config.half_duplex {
@@ -801,8 +1012,11 @@ impl<'d, M: PeriMode> Spi<'d, M> {
 
         // SPI format init
         r.trans_fmt().modify(|w| {
+            // restored for single IO transfers, after a dual or quad transfer
             if config.data_phase == DataPhaseFormat::DUAL_IO || config.data_phase == DataPhaseFormat::QUAD_IO {
                 w.set_mosibidir(true);
+            } else {
+                w.set_mosibidir(self.half_duplex);
             }
             w.set_addrlen(config.addr_len);
         });
@@ -924,8 +1138,8 @@ impl<'d, M: PeriMode> Spi<'d, M> {
         let r = self.info.regs;
         let config = TransferConfig::default();
 
-        self.configure_transfer(data.len(), 0, &config)?;
         self.set_word_size(W::CONFIG);
+        self.configure_transfer(data.len(), 0, &config)?;
 
         // Write data byte by byte
         for b in data {
@@ -950,8 +1164,8 @@ impl<'d, M: PeriMode> Spi<'d, M> {
         let mut config = TransferConfig::default();
         config.transfer_mode = TransMode::READ_ONLY;
 
-        self.configure_transfer(0, data.len(), &config)?;
         self.set_word_size(W::CONFIG);
+        self.configure_transfer(0, data.len(), &config)?;
 
         for b in data {
             // while r.status().read().rxempty() {}
@@ -971,8 +1185,8 @@ impl<'d, M: PeriMode> Spi<'d, M> {
     ) -> Result<(), Error> {
         let r = self.info.regs;
 
-        self.configure_transfer(write.len(), read.len(), &config)?;
         self.set_word_size(W::CONFIG);
+        self.configure_transfer(write.len(), read.len(), &config)?;
 
         let mut i = 0;
         let mut j = 0;
@@ -1002,8 +1216,8 @@ impl<'d, M: PeriMode> Spi<'d, M> {
     ) -> Result<(), Error> {
         let r = self.info.regs;
 
-        self.configure_transfer(words.len(), words.len(), &config)?;
         self.set_word_size(W::CONFIG);
+        self.configure_transfer(words.len(), words.len(), &config)?;
 
         let mut i = 0;
         let mut j = 0;
@@ -1042,7 +1256,6 @@ fn flush_rx_fifo(r: crate::pac::spi::Spi) {
 }
 
 struct State {
-    #[allow(unused)]
     waker: AtomicWaker,
 }
 
@@ -1102,6 +1315,11 @@ trait SealedWord {
 }
 
 /// Word sizes usable for SPI.
+///
+/// `u8`, `u16` and `u32` for the common frame sizes, and [`word::U1`] to [`word::U31`]
+/// for any other frame size, e.g. `U9` for 9-bit display controllers or `U24` for 24-bit ADCs.
+/// Each frame is stored right aligned in the smallest primitive type that fits it,
+/// which is also the DMA transfer width.
 #[allow(private_bounds)]
 pub trait Word: word::Word + SealedWord {}
 
@@ -1163,6 +1381,8 @@ impl embedded_hal::spi::Error for Error {
             Error::InvalidArgument => embedded_hal::spi::ErrorKind::Other,
             Error::BufferTooLong => embedded_hal::spi::ErrorKind::Other,
             Error::FifoFull => embedded_hal::spi::ErrorKind::Overrun,
+            Error::Overrun => embedded_hal::spi::ErrorKind::Overrun,
+            Error::Underrun => embedded_hal::spi::ErrorKind::Other,
         }
     }
 }
@@ -1171,16 +1391,16 @@ impl<'d, M: PeriMode> embedded_hal::spi::ErrorType for Spi<'d, M> {
     type Error = Error;
 }
 
-impl<'d, M: PeriMode> embedded_hal::spi::SpiBus for Spi<'d, M> {
-    fn write(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+impl<'d, M: PeriMode, W: Word> embedded_hal::spi::SpiBus<W> for Spi<'d, M> {
+    fn write(&mut self, buf: &[W]) -> Result<(), Self::Error> {
         self.blocking_write(buf)
     }
 
-    fn read(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+    fn read(&mut self, buf: &mut [W]) -> Result<(), Self::Error> {
         self.blocking_read(buf)
     }
 
-    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+    fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), Self::Error> {
         let config = TransferConfig {
             transfer_mode: TransMode::WRITE_READ,
             ..Default::default()
@@ -1188,7 +1408,7 @@ impl<'d, M: PeriMode> embedded_hal::spi::SpiBus for Spi<'d, M> {
         self.blocking_transfer(read, write, &config)
     }
 
-    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+    fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
         let config = TransferConfig {
             transfer_mode: TransMode::WRITE_READ,
             ..Default::default()
@@ -0,0 +1,527 @@
+//! SPI slave (target) mode.
+//!
+//! The HPM SPI slave works in one of two ways:
+//! - Slave data-only mode: data is exchanged as soon as CS is asserted, like most SPI slaves.
+//! - Command mode: the master starts each transaction with a command byte, which is decoded by hardware.
+//!
+//! Commands of the command mode, dual and quad variants need D2/D3 pins, which are not supported here:
+//!
+//! | Command | Single | Dual | Quad |
+//! |---------|--------|------|------|
+//! | Read status, returns `{ready, overrun, underrun, user_status}` | 0x05 | 0x15 | 0x25 |
+//! | Read data, the master reads the TX FIFO | 0x0B | 0x0C | 0x0E |
+//! | Write data, the master writes the RX FIFO | 0x51 | 0x52 | 0x54 |
+//!
+//! A transaction is framed by CS: it ends when the master deasserts CS.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::ptr;
+use core::task::Poll;
+
+use embassy_futures::join::join;
+use embassy_futures::select::select;
+use embassy_futures::yield_now;
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+
+use super::consts::*;
+use super::{
+    BitOrder, CsPin, DataPhaseFormat, Error, Info, Instance, InterruptHandler, MisoPin, Mode, MosiPin, RxDma, SclkPin,
+    SealedWord, State, TransMode, TxDma, Word, MODE_0,
+};
+use crate::dma::{self, ChannelAndRequest};
+use crate::gpio::{AnyPin, SealedPin};
+use crate::internal::timeout::Timeout;
+use crate::interrupt;
+use crate::interrupt::typelevel::Interrupt as _;
+use crate::mode::{Async, Blocking, Mode as PeriMode};
+use crate::pac;
+
+/// Config struct of SPI slave
+#[derive(Clone, Copy)]
+pub struct SlaveConfig {
+    /// Whether to use LSB.
+    pub bit_order: BitOrder,
+    /// Mode
+    pub mode: Mode,
+    /// Slave data-only mode, data is exchanged right after CS is asserted, without a command phase.
+    pub data_only: bool,
+    /// Timeout of the blocking waits, for the master to send a command or to end the transaction.
+    #[cfg(feature = "time")]
+    pub timeout: embassy_time::Duration,
+}
+
+impl Default for SlaveConfig {
+    fn default() -> Self {
+        Self {
+            bit_order: BitOrder::MsbFirst,
+            mode: MODE_0,
+            data_only: true,
+            #[cfg(feature = "time")]
+            timeout: embassy_time::Duration::from_millis(1000),
+        }
+    }
+}
+
+/// Result of a slave transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SlaveTransfer {
+    /// Command sent by the master, `None` in data-only mode.
+    pub command: Option<u8>,
+    /// Number of words received from the master.
+    pub read: usize,
+    /// Number of words sent to the master.
+    pub written: usize,
+}
+
+/// SPI slave driver.
+#[allow(unused)]
+pub struct SpiSlave<'d, M: PeriMode> {
+    info: &'static Info,
+    state: &'static State,
+    sclk: Option<PeripheralRef<'d, AnyPin>>,
+    cs: Option<PeripheralRef<'d, AnyPin>>,
+    mosi: Option<PeripheralRef<'d, AnyPin>>,
+    miso: Option<PeripheralRef<'d, AnyPin>>,
+    tx_dma: Option<ChannelAndRequest<'d>>,
+    rx_dma: Option<ChannelAndRequest<'d>>,
+    data_only: bool,
+    #[cfg(feature = "time")]
+    timeout: embassy_time::Duration,
+    _phantom: PhantomData<M>,
+}
+
+impl<'d> SpiSlave<'d, Blocking> {
+    /// Create a new blocking SPI slave driver.
+    pub fn new_blocking<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        sclk: impl Peripheral<P = impl SclkPin<T>> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        config: SlaveConfig,
+    ) -> Self {
+        into_ref!(sclk, cs, mosi, miso);
+
+        T::add_resource_group(0);
+
+        sclk.set_as_alt(sclk.alt_num());
+        cs.set_as_alt(cs.alt_num());
+        mosi.set_as_alt(mosi.alt_num());
+        miso.set_as_alt(miso.alt_num());
+
+        Self::new_inner(
+            peri,
+            Some(sclk.map_into()),
+            Some(cs.map_into()),
+            Some(mosi.map_into()),
+            Some(miso.map_into()),
+            None,
+            None,
+            config,
+        )
+    }
+}
+
+impl<'d> SpiSlave<'d, Async> {
+    /// Create a new async SPI slave driver, using DMA.
+    pub fn new<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        sclk: impl Peripheral<P = impl SclkPin<T>> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: SlaveConfig,
+    ) -> Self {
+        into_ref!(sclk, cs, mosi, miso);
+
+        T::add_resource_group(0);
+
+        sclk.set_as_alt(sclk.alt_num());
+        cs.set_as_alt(cs.alt_num());
+        mosi.set_as_alt(mosi.alt_num());
+        miso.set_as_alt(miso.alt_num());
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Self::new_inner(
+            peri,
+            Some(sclk.map_into()),
+            Some(cs.map_into()),
+            Some(mosi.map_into()),
+            Some(miso.map_into()),
+            new_dma!(tx_dma),
+            new_dma!(rx_dma),
+            config,
+        )
+    }
+
+    /// Wait for the master to send a command, returns the command.
+    ///
+    /// Only meaningful in command mode. The data phase of the transaction is not handled,
+    /// prepare it with [`transfer`](Self::transfer) right after, before the master reaches the data phase.
+    pub async fn wait_for_command(&mut self) -> u8 {
+        let r = self.info.regs;
+        let s = self.state;
+
+        r.intr_st().write(|w| w.set_slvcmdint(true));
+
+        let _on_drop = OnDrop::new(move || r.intr_en().modify(|w| w.set_slvcmden(false)));
+
+        poll_fn(|cx| {
+            s.waker.register(cx.waker());
+            if r.intr_st().read().slvcmdint() {
+                return Poll::Ready(());
+            }
+            r.intr_en().modify(|w| w.set_slvcmden(true));
+            Poll::Pending
+        })
+        .await;
+
+        r.intr_st().write(|w| w.set_slvcmdint(true));
+
+        r.cmd().read().cmd()
+    }
+
+    /// Respond to one transaction of the master, using DMA.
+    ///
+    /// `write` is sent to the master and `read` is filled with the data from the master, at the same time.
+    /// Returns when the master deasserts CS, the master may exchange fewer words than the buffers hold.
+    pub async fn transfer<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<SlaveTransfer, Error> {
+        let r = self.info.regs;
+        let (read_len, write_len) = (read.len(), write.len());
+
+        self.configure_transfer::<W>(write_len, read_len)?;
+
+        let tx_dst = r.data().as_ptr() as *mut W;
+        let mut opts = dma::TransferOptions::default();
+        opts.burst = dma::Burst::from_size(FIFO_SIZE / 2);
+        let mut tx_f = (!write.is_empty()).then(|| unsafe { self.tx_dma.as_mut().unwrap().write(write, tx_dst, opts) });
+
+        // single burst, so that the last words are moved out of the FIFO
+        let rx_src = r.data().as_ptr() as *mut W;
+        let mut rx_f =
+            (!read.is_empty()).then(|| unsafe { self.rx_dma.as_mut().unwrap().read(rx_src, read, Default::default()) });
+
+        r.ctrl().modify(|w| {
+            w.set_txdmaen(tx_f.is_some());
+            w.set_rxdmaen(rx_f.is_some());
+        });
+
+        let on_drop = OnDrop::new(move || {
+            r.ctrl().modify(|w| {
+                w.set_txdmaen(false);
+                w.set_rxdmaen(false);
+            });
+        });
+
+        // NOTE: SPI end interrupt is not working under DMA mode, the end of the transaction is polled
+        let dma_done = async {
+            join(
+                async {
+                    if let Some(tx_f) = tx_f.as_mut() {
+                        tx_f.await;
+                    }
+                },
+                async {
+                    if let Some(rx_f) = rx_f.as_mut() {
+                        rx_f.await;
+                    }
+                },
+            )
+            .await;
+
+            while r.status().read().spiactive() {
+                yield_now().await;
+            }
+        };
+        select(dma_done, wait_for_cs_release(r)).await;
+
+        // let the DMA drain the RX FIFO
+        if let Some(rx_f) = rx_f.as_mut() {
+            while rx_f.is_running() && !r.status().read().rxempty() {}
+        }
+
+        // stops the DMA transfers, if not completed
+        drop(on_drop);
+        drop(tx_f);
+        drop(rx_f);
+
+        self.finish_transfer(read_len, write_len)
+    }
+
+    /// Receive one transaction from the master, using DMA.
+    pub async fn read<W: Word>(&mut self, data: &mut [W]) -> Result<SlaveTransfer, Error> {
+        self.transfer(data, &[]).await
+    }
+
+    /// Send one transaction to the master, using DMA.
+    pub async fn write<W: Word>(&mut self, data: &[W]) -> Result<SlaveTransfer, Error> {
+        self.transfer(&mut [], data).await
+    }
+}
+
+impl<'d, M: PeriMode> SpiSlave<'d, M> {
+    fn new_inner<T: Instance>(
+        _peri: impl Peripheral<P = T> + 'd,
+        sclk: Option<PeripheralRef<'d, AnyPin>>,
+        cs: Option<PeripheralRef<'d, AnyPin>>,
+        mosi: Option<PeripheralRef<'d, AnyPin>>,
+        miso: Option<PeripheralRef<'d, AnyPin>>,
+        tx_dma: Option<ChannelAndRequest<'d>>,
+        rx_dma: Option<ChannelAndRequest<'d>>,
+        config: SlaveConfig,
+    ) -> Self {
+        let mut this = Self {
+            info: T::info(),
+            state: T::state(),
+            sclk,
+            cs,
+            mosi,
+            miso,
+            tx_dma,
+            rx_dma,
+            data_only: config.data_only,
+            #[cfg(feature = "time")]
+            timeout: config.timeout,
+            _phantom: PhantomData,
+        };
+
+        this.set_config(&config);
+
+        this
+    }
+
+    /// Reconfigure the driver.
+    pub fn set_config(&mut self, config: &SlaveConfig) {
+        let r = self.info.regs;
+
+        // Same as master mode, see `Spi::enable_and_configure`
+        let cpol = config.mode.phase == embedded_hal::spi::Phase::CaptureOnSecondTransition;
+        let cpha = config.mode.polarity == embedded_hal::spi::Polarity::IdleHigh;
+
+        r.trans_fmt().write(|w| {
+            w.set_datalen(<u8 as SealedWord>::CONFIG);
+            w.set_datamerge(false);
+            w.set_mosibidir(false);
+            w.set_lsb(config.bit_order == BitOrder::LsbFirst);
+            w.set_slvmode(true);
+            w.set_cpha(cpha);
+            w.set_cpol(cpol);
+        });
+
+        self.data_only = config.data_only;
+        #[cfg(feature = "time")]
+        {
+            self.timeout = config.timeout;
+        }
+    }
+
+    fn timeout(&self) -> Timeout {
+        Timeout {
+            #[cfg(feature = "time")]
+            deadline: embassy_time::Instant::now() + self.timeout,
+        }
+    }
+
+    /// Set the user status, returned to the master by the read status command.
+    pub fn set_status(&mut self, status: u16) {
+        self.info.regs.slv_st().modify(|w| w.set_usr_status(status));
+    }
+
+    /// Set the ready flag, returned to the master by the read status command.
+    ///
+    /// The flag is set when a transfer is prepared, and cleared by hardware when a transaction
+    /// other than read status ends.
+    pub fn set_ready(&mut self, ready: bool) {
+        self.info.regs.slv_st().modify(|w| w.set_ready(ready));
+    }
+
+    /// The last command sent by the master, in command mode.
+    pub fn last_command(&self) -> u8 {
+        self.info.regs.cmd().read().cmd()
+    }
+
+    /// Wait for the master to send a command, returns the command.
+    ///
+    /// See [`SpiSlave::wait_for_command`]. Returns [`Error::Timeout`] if no command is received in time.
+    pub fn blocking_wait_for_command(&mut self) -> Result<u8, Error> {
+        let r = self.info.regs;
+        let timeout = self.timeout();
+
+        r.intr_st().write(|w| w.set_slvcmdint(true));
+        while !r.intr_st().read().slvcmdint() {
+            timeout.check()?;
+        }
+        r.intr_st().write(|w| w.set_slvcmdint(true));
+
+        Ok(r.cmd().read().cmd())
+    }
+
+    /// Respond to one transaction of the master.
+    ///
+    /// See [`SpiSlave::transfer`].
+    pub fn blocking_transfer<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<SlaveTransfer, Error> {
+        let r = self.info.regs;
+
+        self.configure_transfer::<W>(write.len(), read.len())?;
+
+        let timeout = self.timeout();
+        let mut i = 0;
+        let mut j = 0;
+
+        loop {
+            let status = r.status().read();
+
+            if i < write.len() && !status.txfull() {
+                unsafe { ptr::write_volatile(r.data().as_ptr() as *mut W, write[i]) };
+                i += 1;
+            }
+
+            if j < read.len() && !status.rxempty() {
+                read[j] = unsafe { ptr::read_volatile(r.data().as_ptr() as *const W) };
+                j += 1;
+            }
+
+            if r.intr_st().read().endint() {
+                break;
+            }
+
+            timeout.check()?;
+        }
+
+        // words received right before CS is deasserted
+        while j < read.len() && !r.status().read().rxempty() {
+            read[j] = unsafe { ptr::read_volatile(r.data().as_ptr() as *const W) };
+            j += 1;
+        }
+
+        self.finish_transfer(read.len(), write.len())
+    }
+
+    /// Receive one transaction from the master.
+    pub fn blocking_read<W: Word>(&mut self, data: &mut [W]) -> Result<SlaveTransfer, Error> {
+        self.blocking_transfer(data, &[])
+    }
+
+    /// Send one transaction to the master.
+    pub fn blocking_write<W: Word>(&mut self, data: &[W]) -> Result<SlaveTransfer, Error> {
+        self.blocking_transfer(&mut [], data)
+    }
+
+    fn configure_transfer<W: Word>(&mut self, write_len: usize, read_len: usize) -> Result<(), Error> {
+        if write_len > TRANSFER_COUNT_MAX || read_len > TRANSFER_COUNT_MAX {
+            return Err(Error::BufferTooLong);
+        }
+
+        let r = self.info.regs;
+
+        r.trans_fmt().modify(|w| w.set_datalen(W::CONFIG));
+
+        // The transfer counts can't be zero, an unused direction is just left empty
+        let write_cnt = write_len.max(1) - 1;
+        let read_cnt = read_len.max(1) - 1;
+
+        // slave_data_only mode works with WriteReadTogether mode only.
+        // In command mode, the data direction is decided by the command.
+        r.trans_ctrl().write(|w| {
+            w.set_slvdataonly(self.data_only);
+            w.set_cmden(false);
+            w.set_addren(false);
+            w.set_dualquad(DataPhaseFormat::SINGLE_IO);
+            w.set_tokenen(false);
+            #[cfg(not(ip_feature_spi_new_trans_count))]
+            {
+                w.set_wrtrancnt(write_cnt as u16);
+                w.set_rdtrancnt(read_cnt as u16);
+            }
+            w.set_transmode(TransMode::WRITE_READ_TOGETHER);
+        });
+
+        #[cfg(ip_feature_spi_new_trans_count)]
+        {
+            r.wr_trans_cnt().write(|w| w.set_wrtrancnt(write_cnt as u32));
+            r.rd_trans_cnt().write(|w| w.set_rdtrancnt(read_cnt as u32));
+        }
+
+        // reset txfifo, rxfifo and control
+        r.ctrl().modify(|w| {
+            w.set_txfiforst(true);
+            w.set_rxfiforst(true);
+            w.set_spirst(true);
+        });
+
+        // clear flags of the last transaction, W1C
+        r.intr_st().write(|w| {
+            w.set_endint(true);
+            w.set_slvcmdint(true);
+        });
+        r.slv_st().modify(|w| {
+            w.set_overrun(true);
+            w.set_underrun(true);
+            w.set_ready(true);
+        });
+
+        Ok(())
+    }
+
+    fn finish_transfer(&mut self, read_len: usize, write_len: usize) -> Result<SlaveTransfer, Error> {
+        let r = self.info.regs;
+
+        r.intr_st().write(|w| w.set_endint(true));
+
+        let status = r.slv_st().read();
+        if status.overrun() {
+            return Err(Error::Overrun);
+        }
+        if status.underrun() {
+            return Err(Error::Underrun);
+        }
+
+        let (received, sent) = slave_data_count(r);
+
+        Ok(SlaveTransfer {
+            command: (!self.data_only).then(|| r.cmd().read().cmd()),
+            read: received.min(read_len),
+            written: sent.min(write_len),
+        })
+    }
+}
+
+impl<'d, M: PeriMode> Drop for SpiSlave<'d, M> {
+    fn drop(&mut self) {
+        self.info.regs.trans_fmt().modify(|w| w.set_slvmode(false));
+
+        self.sclk.as_ref().map(|x| x.set_as_default());
+        self.cs.as_ref().map(|x| x.set_as_default());
+        self.mosi.as_ref().map(|x| x.set_as_default());
+        self.miso.as_ref().map(|x| x.set_as_default());
+    }
+}
+
+/// Wait for the master to deassert CS, once the transaction started.
+async fn wait_for_cs_release(r: pac::spi::Spi) {
+    let last_count = slave_data_count(r);
+    let mut started = false;
+    loop {
+        if r.status().read().spiactive() {
+            started = true;
+        } else if started || r.intr_st().read().endint() || slave_data_count(r) != last_count {
+            // a short transaction may start and end between two polls
+            return;
+        }
+        yield_now().await;
+    }
+}
+
+/// Words (received, sent) in the last transaction, counted by hardware.
+fn slave_data_count(r: pac::spi::Spi) -> (usize, usize) {
+    let cnt = r.slv_data_cnt().read();
+    (cnt.rcnt() as usize, cnt.wcnt() as usize)
+}
@@ -0,0 +1,131 @@
+//! Automatic baud rate detection.
+//!
+//! The UART has no hardware auto-baud, so the RX pin is temporarily switched to GPIO,
+//! and the edges of a sync char `0x55` are timed with the `mcycle` counter.
+//!
+//! `0x55` is sent LSB first, so with the start bit, the line toggles on every bit:
+//! the first falling edge (start bit) to the last rising edge (stop bit) is 9 bit times.
+//! The start bit is waited for with interrupts enabled, so it's not timed: the bit rate is measured
+//! from the end of the start bit to the stop bit, 8 bit times, with interrupts disabled.
+
+use riscv::register::mcycle;
+
+use super::{Config, Error, UartRx, HPM_UART_MINIMUM_BAUDRATE};
+use crate::gpio::{Pull, SealedPin};
+use crate::mode::Mode;
+
+/// Sync char used for baud rate detection
+pub const AUTOBAUD_SYNC_CHAR: u8 = 0x55;
+
+/// Number of edges of the sync char, including the start bit falling edge
+const SYNC_CHAR_EDGES: usize = 10;
+
+/// Common baud rates, a detected rate is rounded to one of these if close enough
+const STANDARD_BAUDRATES: &[u32] = &[
+    1200, 2400, 4800, 9600, 14400, 19200, 38400, 57600, 115200, 230400, 460800, 921600, 1000000, 1500000, 2000000,
+    3000000,
+];
+
+/// Tolerance of rounding to a standard baud rate, in percent
+const AUTOBAUD_TOLERANCE: u64 = 3;
+
+/// Tolerance of each bit width to the average bit width, in percent
+const BIT_WIDTH_TOLERANCE: u64 = 30;
+
+impl<'d, M: Mode> UartRx<'d, M> {
+    /// Detect the baud rate from a received sync char `0x55`, then reconfigure the driver to it.
+    ///
+    /// `config` provides the framing, its baud rate is replaced by the detected one.
+    /// Blocks until the sync char is received, or returns [`Error::Timeout`] after `timeout_us` microseconds.
+    /// The line must be idle (high) when called.
+    /// The sync char itself is consumed by the detection, it's not received by the UART.
+    ///
+    /// Returns the detected baud rate, rounded to a common baud rate if within 3%.
+    pub fn blocking_detect_baudrate(&mut self, config: &Config, timeout_us: u32) -> Result<u32, Error> {
+        let Some(rx) = self.rx.as_ref() else {
+            return Err(Error::AutoBaud);
+        };
+
+        let cpu_freq = crate::sysctl::clocks().cpu0.0 as u64;
+        // one bit time at the lowest baud rate, the max time between two edges
+        let max_edge_cycles = cpu_freq / HPM_UART_MINIMUM_BAUDRATE as u64;
+        let timeout_cycles = cpu_freq * timeout_us as u64 / 1_000_000;
+
+        // switch RX pin to GPIO input, pulled up as an idle UART line
+        let func_ctl = rx.ioc_pad().func_ctl().read();
+        let pad_ctl = rx.ioc_pad().pad_ctl().read();
+        rx.set_as_ioc_gpio();
+        rx.set_pull(Pull::Up);
+
+        // edges after the start bit falling edge, 8 bit times apart
+        let mut edges = [0u64; SYNC_CHAR_EDGES - 1];
+
+        // wait for the start bit with interrupts enabled, its falling edge may be delayed by an interrupt
+        // so it's not timed
+        let start = mcycle::read64();
+        let mut measured = Ok(());
+        while rx.is_high() {
+            if mcycle::read64() - start > timeout_cycles {
+                measured = Err(Error::Timeout);
+                break;
+            }
+        }
+
+        // the other edges are timed with interrupts disabled
+        if measured.is_ok() {
+            measured = critical_section::with(|_| {
+                // start bit missed by an interrupt
+                if rx.is_high() {
+                    return Err(Error::AutoBaud);
+                }
+
+                let mut level = false;
+                for edge in edges.iter_mut() {
+                    let start = mcycle::read64();
+                    while rx.is_high() == level {
+                        if mcycle::read64() - start > max_edge_cycles {
+                            return Err(Error::AutoBaud);
+                        }
+                    }
+                    *edge = mcycle::read64();
+                    level = !level;
+                }
+                Ok(())
+            });
+        }
+
+        // restore RX pin
+        rx.ioc_pad().pad_ctl().write_value(pad_ctl);
+        rx.ioc_pad().func_ctl().write_value(func_ctl);
+
+        measured?;
+
+        let bits = edges.len() as u64 - 1;
+        let total_cycles = edges[edges.len() - 1] - edges[0];
+        let bit_cycles = total_cycles / bits;
+        if bit_cycles == 0 {
+            return Err(Error::AutoBaud);
+        }
+
+        // every bit must be about the same width, or it's not a sync char
+        for pair in edges.windows(2) {
+            let width = pair[1] - pair[0];
+            if width.abs_diff(bit_cycles) * 100 > bit_cycles * BIT_WIDTH_TOLERANCE {
+                return Err(Error::AutoBaud);
+            }
+        }
+
+        let measured_baudrate = (cpu_freq * bits / total_cycles) as u32;
+        let baudrate = STANDARD_BAUDRATES
+            .iter()
+            .copied()
+            .find(|&b| (measured_baudrate.abs_diff(b) as u64) * 100 <= b as u64 * AUTOBAUD_TOLERANCE)
+            .unwrap_or(measured_baudrate);
+
+        let mut config = *config;
+        config.baudrate = baudrate;
+        self.set_config(&config).map_err(|_| Error::AutoBaud)?;
+
+        Ok(baudrate)
+    }
+}
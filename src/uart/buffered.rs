@@ -0,0 +1,523 @@
+//! Interrupt-driven buffered UART, without DMA channels.
+//!
+//! Received bytes are moved from the RX FIFO into a user provided ring buffer by the
+//! RX data available (and RX timeout) interrupt, bytes to be sent are moved from another
+//! ring buffer to the TX FIFO by the THR empty interrupt.
+//!
+//! FIFO trigger levels are taken from [`Config::fifo_level`], both the FCRR (fine FIFO threshold)
+//! and the legacy FCR register layout are supported.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::atomic_ring_buffer::RingBuffer;
+use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::{
+    blocking_flush, configure, fifo_enabled, flush, Config, ConfigError, CtsPin, Error, Info, Instance, RtsPin, RxPin,
+    TxPin, UART_FIFO_SIZE,
+};
+use crate::gpio::{AnyPin, SealedPin};
+use crate::interrupt::InterruptExt as _;
+use crate::time::Hertz;
+use crate::{interrupt, pac};
+
+/// Interrupt handler for [`BufferedUart`].
+pub struct BufferedInterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for BufferedInterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        on_interrupt(T::info().regs, T::buffered_state());
+
+        // PLIC ack is handled by typelevel Handler
+    }
+}
+
+unsafe fn on_interrupt(r: pac::uart::Uart, state: &'static State) {
+    // RX
+    let mut lsr = r.lsr().read();
+
+    // R1C, keep the error bits for the reader
+    let errors = error_bits(lsr);
+    if errors != 0 {
+        state.rx_errors.fetch_or(errors, Ordering::Relaxed);
+        state.rx_waker.wake();
+    }
+
+    if lsr.dr() {
+        let mut rx_writer = state.rx_buf.writer();
+
+        while lsr.dr() {
+            if rx_writer.is_full() {
+                // no space left, stop receiving until the reader consumes some data.
+                // Further bytes are kept in the FIFO, or lost as a FIFO overrun.
+                r.ier().modify(|w| w.set_erbi(false));
+                break;
+            }
+            rx_writer.push_one(r.rbr().read().rbr());
+
+            lsr = r.lsr().read();
+            state.rx_errors.fetch_or(error_bits(lsr), Ordering::Relaxed);
+        }
+
+        state.rx_waker.wake();
+    }
+
+    // TX
+    if r.ier().read().ethei() && lsr.thre() {
+        let mut tx_reader = state.tx_buf.reader();
+
+        // THRE: the TX FIFO is empty, fill it up
+        let slots = if fifo_enabled(r) { UART_FIFO_SIZE } else { 1 };
+        for _ in 0..slots {
+            match tx_reader.pop_one() {
+                Some(b) => r.thr().write(|w| w.set_thr(b)),
+                None => break,
+            }
+        }
+
+        if tx_reader.is_empty() {
+            r.ier().modify(|w| w.set_ethei(false));
+            state.tx_done.store(true, Ordering::Relaxed);
+        }
+
+        state.tx_waker.wake();
+    }
+}
+
+/// Keep only the error bits of LSR: PE, FE, OE, ERRF, LBREAK
+fn error_bits(lsr: pac::uart::regs::Lsr) -> u32 {
+    let mut errors = pac::uart::regs::Lsr(0);
+    errors.set_pe(lsr.pe());
+    errors.set_fe(lsr.fe());
+    errors.set_oe(lsr.oe());
+    errors.set_errf(lsr.errf());
+    errors.set_lbreak(lsr.lbreak());
+    errors.0
+}
+
+pub(crate) struct State {
+    rx_waker: AtomicWaker,
+    rx_buf: RingBuffer,
+    rx_errors: AtomicU32,
+    tx_waker: AtomicWaker,
+    tx_buf: RingBuffer,
+    tx_done: AtomicBool,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_waker: AtomicWaker::new(),
+            rx_buf: RingBuffer::new(),
+            rx_errors: AtomicU32::new(0),
+            tx_waker: AtomicWaker::new(),
+            tx_buf: RingBuffer::new(),
+            tx_done: AtomicBool::new(true),
+        }
+    }
+}
+
+/// Bidirectional buffered UART, driven by interrupts.
+///
+/// Useful when no DMA channel is left for the UART.
+pub struct BufferedUart<'d> {
+    info: &'static Info,
+    state: &'static State,
+    kernel_clock: Hertz,
+    baudrate: u32,
+    rx: Option<PeripheralRef<'d, AnyPin>>,
+    tx: Option<PeripheralRef<'d, AnyPin>>,
+    rts: Option<PeripheralRef<'d, AnyPin>>,
+    cts: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d> BufferedUart<'d> {
+    /// Create a new bidirectional buffered UART
+    pub fn new<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, BufferedInterruptHandler<T>> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(rx, tx);
+
+        rx.set_as_alt(rx.alt_num());
+        tx.set_as_alt(tx.alt_num());
+
+        Self::new_inner(
+            peri,
+            Some(rx.map_into()),
+            Some(tx.map_into()),
+            None,
+            None,
+            tx_buffer,
+            rx_buffer,
+            config,
+        )
+    }
+
+    /// Create a new bidirectional buffered UART with request-to-send and clear-to-send pins
+    pub fn new_with_rtscts<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, BufferedInterruptHandler<T>> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        rts: impl Peripheral<P = impl RtsPin<T>> + 'd,
+        cts: impl Peripheral<P = impl CtsPin<T>> + 'd,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(rx, tx, rts, cts);
+
+        rx.set_as_alt(rx.alt_num());
+        tx.set_as_alt(tx.alt_num());
+        rts.set_as_alt(rts.alt_num());
+        cts.set_as_alt(cts.alt_num());
+
+        Self::new_inner(
+            peri,
+            Some(rx.map_into()),
+            Some(tx.map_into()),
+            Some(rts.map_into()),
+            Some(cts.map_into()),
+            tx_buffer,
+            rx_buffer,
+            config,
+        )
+    }
+
+    fn new_inner<T: Instance>(
+        _peri: impl Peripheral<P = T> + 'd,
+        rx: Option<PeripheralRef<'d, AnyPin>>,
+        tx: Option<PeripheralRef<'d, AnyPin>>,
+        rts: Option<PeripheralRef<'d, AnyPin>>,
+        cts: Option<PeripheralRef<'d, AnyPin>>,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        {
+            use crate::sysctl::*;
+            T::set_clock(ClockConfig::new(ClockMux::CLK_24M, 1));
+            T::add_resource_group(0);
+        }
+
+        let info = T::info();
+        let state = T::buffered_state();
+        let kernel_clock = T::frequency();
+
+        assert!(!tx_buffer.is_empty());
+        assert!(!rx_buffer.is_empty());
+
+        state.rx_errors.store(0, Ordering::Relaxed);
+        state.tx_done.store(true, Ordering::Relaxed);
+        unsafe {
+            state.tx_buf.init(tx_buffer.as_mut_ptr(), tx_buffer.len());
+            state.rx_buf.init(rx_buffer.as_mut_ptr(), rx_buffer.len());
+        }
+
+        let mut this = Self {
+            info,
+            state,
+            kernel_clock,
+            baudrate: config.baudrate,
+            rx,
+            tx,
+            rts,
+            cts,
+        };
+
+        this.enable_and_configure(&config)?;
+
+        Ok(this)
+    }
+
+    fn enable_and_configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        let info = self.info;
+
+        info.interrupt.disable();
+
+        configure(info, self.kernel_clock, config, true, true)?;
+        self.baudrate = config.baudrate;
+
+        // clear error flags
+        let _ = info.regs.lsr().read();
+
+        info.regs.ier().modify(|w| {
+            w.set_erbi(true); // rx data available and rx timeout
+            w.set_elsi(true); // rx status
+        });
+
+        info.interrupt.unpend();
+        unsafe { info.interrupt.enable() };
+
+        Ok(())
+    }
+
+    /// Reconfigure the driver
+    ///
+    /// Pending TX data is flushed first, data in the RX ring buffer is kept.
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        // errors here mean the line is stuck, reconfiguring is still the right thing to do
+        let _ = self.blocking_flush();
+
+        self.enable_and_configure(config)
+    }
+
+    /// Read bytes from the RX ring buffer, waits until at least one byte is available.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        read(self.info, self.state, buf).await
+    }
+
+    /// Read bytes from the RX ring buffer, blocks until at least one byte is available.
+    pub fn blocking_read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            if let Poll::Ready(res) = try_read(self.info, self.state, buf) {
+                return res;
+            }
+        }
+    }
+
+    /// Write bytes into the TX ring buffer, waits until at least one byte can be written.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        write(self.info, self.state, buf).await
+    }
+
+    /// Write bytes into the TX ring buffer, blocks until at least one byte can be written.
+    pub fn blocking_write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        loop {
+            if let Poll::Ready(res) = try_write(self.info, self.state, buf) {
+                return res;
+            }
+        }
+    }
+
+    /// Wait until all data in the TX ring buffer is sent
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        let state = self.state;
+
+        poll_fn(move |cx| {
+            state.tx_waker.register(cx.waker());
+
+            if state.tx_done.load(Ordering::Relaxed) && state.tx_buf.is_empty() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        // the last bytes are still in the shift register
+        flush(self.info, self.baudrate).await
+    }
+
+    /// Block until all data in the TX ring buffer is sent
+    pub fn blocking_flush(&mut self) -> Result<(), Error> {
+        while !(self.state.tx_done.load(Ordering::Relaxed) && self.state.tx_buf.is_empty()) {}
+
+        blocking_flush(self.info, self.baudrate)
+    }
+
+    /// Wait until data is available in the RX ring buffer, returns it without consuming.
+    ///
+    /// Call [`consume`](Self::consume) to mark the bytes as read.
+    pub async fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        let info = self.info;
+        let state = self.state;
+
+        poll_fn(move |cx| {
+            state.rx_waker.register(cx.waker());
+
+            take_rx_error(state)?;
+
+            let mut rx_reader = unsafe { state.rx_buf.reader() };
+            let (p, n) = rx_reader.pop_buf();
+            if n == 0 {
+                enable_rx_interrupt(info);
+                return Poll::Pending;
+            }
+
+            let buf = unsafe { slice::from_raw_parts(p, n) };
+            Poll::Ready(Ok(buf))
+        })
+        .await
+    }
+
+    /// Mark `amt` bytes returned by [`fill_buf`](Self::fill_buf) as read.
+    pub fn consume(&mut self, amt: usize) {
+        let mut rx_reader = unsafe { self.state.rx_buf.reader() };
+        rx_reader.pop_done(amt);
+
+        enable_rx_interrupt(self.info);
+    }
+}
+
+fn take_rx_error(state: &State) -> Result<(), Error> {
+    let errors = state.rx_errors.swap(0, Ordering::Relaxed);
+    if errors == 0 {
+        return Ok(());
+    }
+
+    let lsr = pac::uart::regs::Lsr(errors);
+    if lsr.pe() {
+        Err(Error::Parity)
+    } else if lsr.fe() {
+        Err(Error::Framing)
+    } else if lsr.oe() {
+        Err(Error::Overrun)
+    } else if lsr.errf() {
+        Err(Error::FIFO)
+    } else {
+        Err(Error::LineBreak)
+    }
+}
+
+/// Re-enable the RX interrupt, it's disabled by the interrupt handler when the RX ring buffer is full.
+fn enable_rx_interrupt(info: &Info) {
+    critical_section::with(|_| info.regs.ier().modify(|w| w.set_erbi(true)));
+}
+
+fn try_read(info: &Info, state: &State, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+    if buf.is_empty() {
+        return Poll::Ready(Ok(0));
+    }
+
+    take_rx_error(state)?;
+
+    let mut rx_reader = unsafe { state.rx_buf.reader() };
+    let data = rx_reader.pop_slice();
+    let n = data.len().min(buf.len());
+    buf[..n].copy_from_slice(&data[..n]);
+    rx_reader.pop_done(n);
+
+    // some space is freed up, or the ring buffer is empty
+    enable_rx_interrupt(info);
+
+    if n == 0 {
+        Poll::Pending
+    } else {
+        Poll::Ready(Ok(n))
+    }
+}
+
+async fn read(info: &Info, state: &State, buf: &mut [u8]) -> Result<usize, Error> {
+    poll_fn(move |cx| {
+        state.rx_waker.register(cx.waker());
+
+        try_read(info, state, buf)
+    })
+    .await
+}
+
+fn try_write(info: &Info, state: &State, buf: &[u8]) -> Poll<Result<usize, Error>> {
+    if buf.is_empty() {
+        return Poll::Ready(Ok(0));
+    }
+
+    let mut tx_writer = unsafe { state.tx_buf.writer() };
+    let n = tx_writer.push_slice(buf);
+    if n == 0 {
+        return Poll::Pending;
+    }
+
+    state.tx_done.store(false, Ordering::Relaxed);
+
+    // THR empty interrupt fires immediately if the TX FIFO is empty
+    critical_section::with(|_| info.regs.ier().modify(|w| w.set_ethei(true)));
+
+    Poll::Ready(Ok(n))
+}
+
+async fn write(info: &Info, state: &State, buf: &[u8]) -> Result<usize, Error> {
+    poll_fn(move |cx| {
+        state.tx_waker.register(cx.waker());
+
+        try_write(info, state, buf)
+    })
+    .await
+}
+
+impl Drop for BufferedUart<'_> {
+    fn drop(&mut self) {
+        let r = self.info.regs;
+
+        self.info.interrupt.disable();
+        r.ier().modify(|w| {
+            w.set_erbi(false);
+            w.set_ethei(false);
+            w.set_elsi(false);
+        });
+
+        unsafe {
+            self.state.rx_buf.deinit();
+            self.state.tx_buf.deinit();
+        }
+
+        self.rx.as_ref().map(|x| x.set_as_default());
+        self.tx.as_ref().map(|x| x.set_as_default());
+        self.rts.as_ref().map(|x| x.set_as_default());
+        self.cts.as_ref().map(|x| x.set_as_default());
+
+        crate::sysctl::clock_remove_from_group(self.info.resource, 0);
+    }
+}
+
+// ==========
+// eh traits
+
+impl embedded_io::ErrorType for BufferedUart<'_> {
+    type Error = Error;
+}
+
+impl embedded_io::Read for BufferedUart<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.blocking_read(buf)
+    }
+}
+
+impl embedded_io::Write for BufferedUart<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.blocking_write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.blocking_flush()
+    }
+}
+
+impl embedded_io_async::Read for BufferedUart<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read(buf).await
+    }
+}
+
+impl embedded_io_async::BufRead for BufferedUart<'_> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        self.fill_buf().await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consume(amt)
+    }
+}
+
+impl embedded_io_async::Write for BufferedUart<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush().await
+    }
+}
@@ -0,0 +1,496 @@
+//! LIN, Local Interconnect Network, on top of the UART driver.
+//!
+//! A LIN frame is a header sent by the master: break field, sync byte `0x55` and protected ID,
+//! followed by a response of 1 to 8 data bytes and a checksum, sent by the master or a slave.
+//!
+//! The break field is generated with LCR.BC, and detected on the receiving side by LSR.LBREAK.
+//! LIN transceivers echo the transmitted bytes back to RX, the echo is discarded by this driver.
+
+use core::future::poll_fn;
+use core::sync::atomic::Ordering;
+use core::task::Poll;
+
+use embedded_hal::delay::DelayNs;
+use riscv::delay::McycleDelay;
+
+use super::{Config, DataBits, Parity, StopBits, Uart};
+use crate::internal::timeout::{Elapsed, Timeout};
+use crate::mode::{Async, Mode};
+use crate::pac;
+
+/// Sync byte of a LIN header
+pub const SYNC_BYTE: u8 = 0x55;
+
+/// Min length of a break field, in bit times
+pub const MIN_BREAK_BITS: u8 = 13;
+
+/// Max number of data bytes in a LIN response
+pub const MAX_DATA_LEN: usize = 8;
+
+/// LIN checksum model
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChecksumType {
+    /// LIN 1.x, sum of data bytes only
+    Classic,
+    /// LIN 2.x, sum of protected ID and data bytes.
+    ///
+    /// Diagnostic frames (ID 0x3C and 0x3D) always use the classic checksum.
+    Enhanced,
+}
+
+/// LIN config
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinConfig {
+    /// Baud rate, up to 20kbps
+    pub baudrate: u32,
+    /// Length of the break field sent by the master, in bit times, at least [`MIN_BREAK_BITS`]
+    pub break_bits: u8,
+    /// Checksum model
+    pub checksum: ChecksumType,
+}
+
+impl Default for LinConfig {
+    fn default() -> Self {
+        Self {
+            baudrate: 19200,
+            break_bits: 13,
+            checksum: ChecksumType::Enhanced,
+        }
+    }
+}
+
+/// LIN error
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// Error from the underlying UART
+    Uart(super::Error),
+    /// Sync byte is not 0x55
+    Sync,
+    /// Parity bits of the protected ID mismatch
+    Pid,
+    /// Checksum mismatch
+    Checksum,
+    /// Frame ID is out of range, must be 0 to 0x3F
+    InvalidId,
+    /// Response is longer than 8 bytes
+    BufferTooLong,
+    /// No response in time
+    Timeout,
+}
+
+/// LIN config error
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// Config error from the underlying UART
+    Uart(super::ConfigError),
+    /// Break field shorter than [`MIN_BREAK_BITS`], see [`LinConfig::break_bits`]
+    BreakTooShort,
+}
+
+impl From<super::ConfigError> for ConfigError {
+    fn from(err: super::ConfigError) -> Self {
+        ConfigError::Uart(err)
+    }
+}
+
+impl From<super::Error> for Error {
+    fn from(err: super::Error) -> Self {
+        Error::Uart(err)
+    }
+}
+
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        Error::Timeout
+    }
+}
+
+/// Protected ID of a frame ID, i.e. the 6-bit ID with its 2 parity bits
+pub fn protected_id(id: u8) -> u8 {
+    let id = id & 0x3F;
+    let bit = |n: u8| (id >> n) & 1;
+
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Frame ID of a protected ID, `Error::Pid` on parity mismatch
+pub fn frame_id(pid: u8) -> Result<u8, Error> {
+    let id = pid & 0x3F;
+    if protected_id(id) == pid {
+        Ok(id)
+    } else {
+        Err(Error::Pid)
+    }
+}
+
+/// Checksum of a LIN response
+pub fn checksum(checksum_type: ChecksumType, pid: u8, data: &[u8]) -> u8 {
+    let id = pid & 0x3F;
+    let mut sum: u16 = match checksum_type {
+        ChecksumType::Enhanced if id != 0x3C && id != 0x3D => pid as u16,
+        _ => 0,
+    };
+
+    for &b in data {
+        sum += b as u16;
+        if sum > 0xFF {
+            sum -= 0xFF;
+        }
+    }
+
+    !(sum as u8)
+}
+
+/// LIN driver, master and slave.
+pub struct Lin<'d, M: Mode> {
+    uart: Uart<'d, M>,
+    config: LinConfig,
+}
+
+impl<'d, M: Mode> Lin<'d, M> {
+    /// Create a new LIN driver.
+    ///
+    /// The UART is reconfigured with `uart_config`, e.g. for FIFO levels or RS-485 DE,
+    /// as 8N1 at the LIN baud rate of `config`.
+    pub fn new(mut uart: Uart<'d, M>, mut uart_config: Config, config: LinConfig) -> Result<Self, ConfigError> {
+        if config.break_bits < MIN_BREAK_BITS {
+            return Err(ConfigError::BreakTooShort);
+        }
+
+        uart_config.baudrate = config.baudrate;
+        uart_config.data_bits = DataBits::DataBits8;
+        uart_config.stop_bits = StopBits::STOP1;
+        uart_config.parity = Parity::ParityNone;
+        uart.set_config(&uart_config)?;
+
+        Ok(Self { uart, config })
+    }
+
+    /// Release the UART
+    pub fn free(self) -> Uart<'d, M> {
+        self.uart
+    }
+
+    /// Send a break field of `config.break_bits` bit times, followed by a one bit break delimiter.
+    pub fn blocking_send_break(&mut self) -> Result<(), Error> {
+        self.uart.blocking_flush()?;
+
+        let r = self.uart.rx.info.regs;
+        let mut delay = McycleDelay::new(crate::sysctl::clocks().cpu0.0);
+
+        r.lcr().modify(|w| w.set_bc(true));
+        delay.delay_us(self.bit_time_us(self.config.break_bits as u32));
+        r.lcr().modify(|w| w.set_bc(false));
+        delay.delay_us(self.bit_time_us(1));
+
+        Ok(())
+    }
+
+    /// Send a LIN header as master: break, sync and protected ID.
+    pub fn blocking_send_header(&mut self, id: u8) -> Result<(), Error> {
+        if id > 0x3F {
+            return Err(Error::InvalidId);
+        }
+
+        self.blocking_send_break()?;
+        self.uart.blocking_write(&[SYNC_BYTE, protected_id(id)])?;
+        self.uart.blocking_flush()?;
+        self.drain_rx();
+
+        Ok(())
+    }
+
+    /// Master request: send a header and the response data with checksum.
+    pub fn blocking_write_frame(&mut self, id: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        self.blocking_send_header(id)?;
+        self.blocking_write_response(id, data)
+    }
+
+    /// Master request: send a header, then receive the response of a slave into `buf`.
+    pub fn blocking_read_frame(&mut self, id: u8, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        self.blocking_send_header(id)?;
+        self.blocking_read_response(id, buf)
+    }
+
+    /// Slave: wait for a LIN header, returns the frame ID.
+    ///
+    /// Received bytes other than a header are dropped.
+    pub fn blocking_read_header(&mut self) -> Result<u8, Error> {
+        let r = self.uart.rx.info.regs;
+
+        // wait for the break field
+        loop {
+            let lsr = r.lsr().read(); // reading clears error flag
+            if lsr.lbreak() {
+                break;
+            } else if lsr.dr() {
+                let _ = r.rbr().read();
+            }
+        }
+        // the break field is received as a 0x00 char
+        self.drain_break_char();
+
+        let mut header = [0u8; 2];
+        let timeout = self.timeout(2);
+        self.blocking_read_timeout(&mut header, timeout)?;
+
+        if header[0] != SYNC_BYTE {
+            return Err(Error::Sync);
+        }
+        frame_id(header[1])
+    }
+
+    /// Send the response of frame `id`, with checksum.
+    pub fn blocking_write_response(&mut self, id: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        let frame = self.response_frame(id, data);
+        self.uart.blocking_write(&frame[..data.len() + 1])?;
+        self.uart.blocking_flush()?;
+        self.drain_rx();
+
+        Ok(())
+    }
+
+    /// Receive the response of frame `id` into `buf`, and verify the checksum.
+    pub fn blocking_read_response(&mut self, id: u8, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        let mut frame = [0u8; MAX_DATA_LEN + 1];
+        let len = buf.len();
+        let timeout = self.timeout(len + 1);
+        self.blocking_read_timeout(&mut frame[..len + 1], timeout)?;
+
+        self.check_response(id, &frame[..len + 1], buf)
+    }
+
+    fn blocking_read_timeout(&mut self, buf: &mut [u8], timeout: Timeout) -> Result<(), Error> {
+        let r = self.uart.rx.info.regs;
+
+        for b in buf {
+            while !self.uart.rx.check_rx_flags()? {
+                timeout.check()?;
+            }
+            *b = r.rbr().read().rbr();
+        }
+
+        Ok(())
+    }
+
+    /// Response data followed by checksum
+    fn response_frame(&self, id: u8, data: &[u8]) -> [u8; MAX_DATA_LEN + 1] {
+        let mut frame = [0u8; MAX_DATA_LEN + 1];
+        frame[..data.len()].copy_from_slice(data);
+        frame[data.len()] = checksum(self.config.checksum, protected_id(id), data);
+        frame
+    }
+
+    fn check_response(&self, id: u8, frame: &[u8], buf: &mut [u8]) -> Result<(), Error> {
+        let (data, sum) = frame.split_at(frame.len() - 1);
+        if checksum(self.config.checksum, protected_id(id), data) != sum[0] {
+            return Err(Error::Checksum);
+        }
+
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Drop received bytes, e.g. the echo of the transceiver
+    fn drain_rx(&mut self) {
+        let r = self.uart.rx.info.regs;
+
+        // reading LSR clears error flags
+        while r.lsr().read().dr() {
+            let _ = r.rbr().read();
+        }
+    }
+
+    /// Drop the 0x00 char of a received break field, keeping any following sync byte
+    fn drain_break_char(&mut self) {
+        let r = self.uart.rx.info.regs;
+
+        while r.lsr().read().dr() {
+            if r.rbr().read().rbr() == 0x00 {
+                break;
+            }
+        }
+    }
+
+    fn bit_time_us(&self, bits: u32) -> u32 {
+        (bits * 1_000_000).div_ceil(self.config.baudrate)
+    }
+
+    /// Timeout of receiving `nbytes`, 140% of the nominal time as in the LIN spec.
+    #[allow(unused_variables)]
+    fn timeout(&self, nbytes: usize) -> Timeout {
+        Timeout {
+            #[cfg(feature = "time")]
+            deadline: embassy_time::Instant::now()
+                + embassy_time::Duration::from_micros(self.bit_time_us(nbytes as u32 * 10 * 14 / 10) as u64),
+        }
+    }
+}
+
+impl<'d> Lin<'d, Async> {
+    /// Send a break field of `config.break_bits` bit times, followed by a one bit break delimiter.
+    pub async fn send_break(&mut self) -> Result<(), Error> {
+        self.uart.tx.flush().await?;
+
+        let r = self.uart.rx.info.regs;
+
+        r.lcr().modify(|w| w.set_bc(true));
+        self.delay_us(self.bit_time_us(self.config.break_bits as u32)).await;
+        r.lcr().modify(|w| w.set_bc(false));
+        self.delay_us(self.bit_time_us(1)).await;
+
+        Ok(())
+    }
+
+    /// Send a LIN header as master: break, sync and protected ID.
+    pub async fn send_header(&mut self, id: u8) -> Result<(), Error> {
+        if id > 0x3F {
+            return Err(Error::InvalidId);
+        }
+
+        self.send_break().await?;
+        self.uart.write(&[SYNC_BYTE, protected_id(id)]).await?;
+        self.uart.tx.flush().await?;
+        self.drain_rx();
+
+        Ok(())
+    }
+
+    /// Master request: send a header and the response data with checksum.
+    pub async fn write_frame(&mut self, id: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        self.send_header(id).await?;
+        self.write_response(id, data).await
+    }
+
+    /// Master request: send a header, then receive the response of a slave into `buf`.
+    pub async fn read_frame(&mut self, id: u8, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        self.send_header(id).await?;
+        self.read_response(id, buf).await
+    }
+
+    /// Slave: wait for a LIN header, returns the frame ID.
+    ///
+    /// Received bytes other than a header are dropped.
+    pub async fn read_header(&mut self) -> Result<u8, Error> {
+        let r = self.uart.rx.info.regs;
+        let s = self.uart.rx.state;
+
+        let _ = r.lsr().read(); // clear error flags
+        s.saved_lsr.store(0, Ordering::Relaxed);
+
+        // wait for the break field, reported by the line status interrupt
+        poll_fn(|cx| {
+            s.rx_waker.register(cx.waker());
+
+            let lsr = pac::uart::regs::Lsr(s.saved_lsr.swap(0, Ordering::Relaxed));
+            if lsr.lbreak() {
+                return Poll::Ready(());
+            }
+
+            // the line status interrupt is disabled by the interrupt handler on errors
+            r.ier().modify(|w| w.set_elsi(true));
+
+            // bytes of other frames, keep the FIFO from overrunning.
+            // Reading LSR clears LBREAK, so check it on each read.
+            loop {
+                let lsr = r.lsr().read();
+                if lsr.lbreak() {
+                    return Poll::Ready(());
+                }
+                if !lsr.dr() {
+                    break;
+                }
+                let _ = r.rbr().read();
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        // the break field is received as a 0x00 char
+        self.drain_break_char();
+
+        let mut header = [0u8; 2];
+        let timeout = self.timeout(2);
+        timeout
+            .with(async { Ok::<_, Error>(self.uart.read(&mut header).await?) })
+            .await?;
+
+        if header[0] != SYNC_BYTE {
+            return Err(Error::Sync);
+        }
+        frame_id(header[1])
+    }
+
+    /// Send the response of frame `id`, with checksum.
+    pub async fn write_response(&mut self, id: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        let frame = self.response_frame(id, data);
+        self.uart.write(&frame[..data.len() + 1]).await?;
+        self.uart.tx.flush().await?;
+        self.drain_rx();
+
+        Ok(())
+    }
+
+    /// Receive the response of frame `id` into `buf`, and verify the checksum.
+    pub async fn read_response(&mut self, id: u8, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() > MAX_DATA_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        let mut frame = [0u8; MAX_DATA_LEN + 1];
+        let len = buf.len();
+        let timeout = self.timeout(len + 1);
+        timeout
+            .with(async { Ok::<_, Error>(self.uart.read(&mut frame[..len + 1]).await?) })
+            .await?;
+
+        self.check_response(id, &frame[..len + 1], buf)
+    }
+
+    async fn delay_us(&self, us: u32) {
+        #[cfg(feature = "time")]
+        embassy_time::Timer::after_micros(us as u64).await;
+
+        #[cfg(not(feature = "time"))]
+        McycleDelay::new(crate::sysctl::clocks().cpu0.0).delay_us(us);
+    }
+}
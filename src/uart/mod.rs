@@ -15,16 +15,21 @@
 
 use core::future::poll_fn;
 use core::marker::PhantomData;
-use core::sync::atomic::{compiler_fence, AtomicU32, AtomicU8, Ordering};
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU32, AtomicU8, Ordering};
 use core::task::Poll;
 
+use embassy_futures::yield_now;
 use embassy_hal_internal::drop::OnDrop;
 use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
+use embedded_hal::delay::DelayNs;
 use futures_util::future::{select, Either};
+use riscv::delay::McycleDelay;
+use riscv::register::mcycle;
 
+use crate::dma::word::Word;
 use crate::dma::ChannelAndRequest;
-use crate::gpio::{AnyPin, SealedPin};
+use crate::gpio::{AnyPin, Pin as _, SealedPin};
 use crate::interrupt::typelevel::Interrupt as _;
 use crate::interrupt::InterruptExt as _;
 use crate::mode::{Async, Blocking, Mode};
@@ -34,7 +39,24 @@ use crate::sysctl::SealedClockPeripheral;
 use crate::time::Hertz;
 use crate::{interrupt, pac};
 
+mod buffered;
+pub use buffered::*;
+
+pub mod lin;
+
+mod autobaud;
+pub use autobaud::AUTOBAUD_SYNC_CHAR;
+
+#[cfg(ip_feature_dma_v2)]
+mod ringbuffered;
+#[cfg(ip_feature_dma_v2)]
+pub use ringbuffered::RingBufferedUartRx;
+
 const HPM_UART_DRV_RETRY_COUNT: u32 = 5000;
+const HPM_UART_MINIMUM_BAUDRATE: u32 = 200;
+
+/// Depth of the TX and RX FIFO
+const UART_FIFO_SIZE: usize = 16;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -48,9 +70,11 @@ pub enum DataBits {
     DataBits7,
     /// 8 Data Bits
     DataBits8,
-    // 9 data bits is not supported by this driver yet
-    // /// 9 Data Bits
-    // DataBits9,
+    /// 9 Data Bits, the 9th bit marks an address frame on multidrop buses
+    ///
+    /// Use the `u16` word API, e.g. [`UartTx::blocking_write_u16`], to access the 9th bit.
+    #[cfg(ip_feature_uart_9bit_mode)]
+    DataBits9,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -92,6 +116,26 @@ pub enum ConfigError {
     BaudrateTooHigh,
     /// Rx or Tx not enabled
     RxOrTxNotEnabled,
+    /// Address match requires 9 data bits
+    #[cfg(ip_feature_uart_addr_match)]
+    AddressMatchRequires9Bits,
+    /// A DMA transfer is in flight, see [`UartConfigHandle`]
+    DmaInFlight,
+    /// TX data is not drained in time, see [`UartConfigHandle`]
+    TxNotDrained,
+    /// DE settings can't be changed by [`UartConfigHandle::set_config`]
+    DeChanged,
+}
+
+/// Node addresses of the hardware address matcher, see [`Config::address_match`]
+#[cfg(ip_feature_uart_addr_match)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AddressMatch {
+    /// Node address
+    pub addr0: u8,
+    /// Optional second address, e.g. a broadcast address
+    pub addr1: Option<u8>,
 }
 
 /// FIFO trigger level, 1 to 16
@@ -115,6 +159,50 @@ pub enum FifoTriggerLevel {
     Byte16 = 15,
 }
 
+/// RS-485 driver enable (DE) polarity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DePolarity {
+    /// DE is high while transmitting
+    ActiveHigh,
+    /// DE is low while transmitting
+    ActiveLow,
+}
+
+/// RS-485 driver enable (DE) settings, used when the UART is created with a DE pin.
+///
+/// The DE function of the UART IP asserts DE active high, for the duration of the transmission only,
+/// it has no polarity or guard time setting. Other settings drive the DE pin as GPIO from the driver:
+/// DE is asserted before a write, and deasserted after the transmitter is empty (LSR.TEMT).
+/// The wait for TEMT is bounded by the time of sending a full FIFO, async writes yield while waiting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeConfig {
+    /// DE polarity
+    pub polarity: DePolarity,
+    /// Delay from DE assertion to the start bit of the first byte, in bit periods
+    pub pre_tx_delay: u8,
+    /// Delay from the end of the last stop bit to DE deassertion, in bit periods
+    pub post_tx_delay: u8,
+}
+
+impl Default for DeConfig {
+    fn default() -> Self {
+        Self {
+            polarity: DePolarity::ActiveHigh,
+            pre_tx_delay: 0,
+            post_tx_delay: 0,
+        }
+    }
+}
+
+impl DeConfig {
+    /// Whether the DE function of the UART IP can be used
+    fn is_hardware(&self) -> bool {
+        self.polarity == DePolarity::ActiveHigh && self.pre_tx_delay == 0 && self.post_tx_delay == 0
+    }
+}
+
 #[non_exhaustive]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Config {
@@ -137,6 +225,15 @@ pub struct Config {
     ///
     /// If false: the error is ignored and cleared
     pub detect_previous_overrun: bool,
+    /// Hardware address match for multidrop buses, requires [`DataBits::DataBits9`]
+    ///
+    /// The receiver drops all frames until an address frame (9th bit set) matching one of the addresses
+    /// is received, then data frames are received until an address frame that doesn't match.
+    /// The matching address frame itself is received too.
+    #[cfg(ip_feature_uart_addr_match)]
+    pub address_match: Option<AddressMatch>,
+    /// RS-485 driver enable settings
+    pub de: DeConfig,
 }
 
 impl Default for Config {
@@ -152,6 +249,9 @@ impl Default for Config {
             fifo_level: Some((FifoTriggerLevel::Byte16, FifoTriggerLevel::Byte1)),
             // no detect
             detect_previous_overrun: false,
+            #[cfg(ip_feature_uart_addr_match)]
+            address_match: None,
+            de: DeConfig::default(),
         }
     }
 }
@@ -175,6 +275,19 @@ pub enum Error {
     Timeout,
     /// Line break
     LineBreak,
+    /// Baud rate detection failed, no valid sync char received
+    AutoBaud,
+}
+
+/// Hardware-triggered transmission config, see [`UartTx::arm_triggered_write`]
+#[cfg(ip_feature_uart_trig_mode)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TriggerConfig {
+    /// Insert extra stop bits between frames, number of bit times
+    pub stop_bit_insert: Option<u8>,
+    /// Clear the RX FIFO on trigger, useful for request-response protocols
+    pub clear_rx_fifo: bool,
 }
 
 enum ReadCompletionEvent {
@@ -264,10 +377,42 @@ pub struct UartTx<'d, M: Mode> {
     tx: Option<PeripheralRef<'d, AnyPin>>,
     cts: Option<PeripheralRef<'d, AnyPin>>,
     de: Option<PeripheralRef<'d, AnyPin>>,
+    de_alt_num: u8,
+    de_config: DeConfig,
+    half_duplex: bool,
     tx_dma: Option<ChannelAndRequest<'d>>,
     _phantom: PhantomData<M>,
 }
 
+/// The line taken by a write, released when the write ends or is cancelled
+struct TxLine<'d> {
+    info: &'static Info,
+    de: Option<PeripheralRef<'d, AnyPin>>,
+    de_polarity: DePolarity,
+    half_duplex: bool,
+}
+
+impl TxLine<'_> {
+    /// Deassert software DE, and enable the receiver again in half-duplex mode
+    fn release(&self) {
+        if let Some(de) = &self.de {
+            if self.de_polarity == DePolarity::ActiveHigh {
+                de.set_low();
+            } else {
+                de.set_high();
+            }
+        }
+        if self.half_duplex {
+            // drop the echo of our own transmission, if the receiver can't be disabled
+            let r = self.info.regs;
+            while r.lsr().read().dr() {
+                let _ = r.rbr().read();
+            }
+            set_receiver_enabled(self.info, true);
+        }
+    }
+}
+
 impl<'d> UartTx<'d, Async> {
     /// Useful if you only want Uart Tx. It saves 1 pin and consumes a little less power.
     pub fn new<T: Instance>(
@@ -310,6 +455,45 @@ impl<'d> UartTx<'d, Async> {
     /// Initiate an asynchronous UART write
     /// Ref: HPM6700_6400_Errata_V2_0.pdf "E00018：UART DMA 请求使用限制"
     pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        self.inner_write(buffer).await
+    }
+
+    /// Initiate an asynchronous UART write of 9-bit words
+    ///
+    /// Bit 8 of each word is the 9th data bit, set it to send an address frame.
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub async fn write_u16(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        self.inner_write(buffer).await
+    }
+
+    async fn inner_write<W: Word>(&mut self, buffer: &[W]) -> Result<(), Error> {
+        self.begin_tx();
+        // release the line if the write is cancelled
+        let line = self.tx_line();
+        let on_drop = OnDrop::new(move || line.release());
+
+        if self.software_de().is_some() {
+            delay_us(self.bit_periods_us(self.de_config.pre_tx_delay)).await;
+        }
+
+        let res = self.inner_write_dma(buffer).await;
+
+        if self.waits_tx_end() {
+            let flush_res = flush(self.info, self.baudrate()).await;
+            if self.software_de().is_some() {
+                delay_us(self.bit_periods_us(self.de_config.post_tx_delay)).await;
+            }
+            on_drop.defuse();
+            self.finish_tx();
+
+            return res.and(flush_res);
+        }
+
+        on_drop.defuse();
+        res
+    }
+
+    async fn inner_write_dma<W: Word>(&mut self, buffer: &[W]) -> Result<(), Error> {
         let r = self.info.regs;
 
         #[cfg(ip_feature_uart_fine_fifo_thrld)]
@@ -323,13 +507,19 @@ impl<'d> UartTx<'d, Async> {
             r.fcr().write_value(fcr);
         }
 
+        let state = self.state;
+        state.tx_dma_busy.store(true, Ordering::Relaxed);
+        let on_drop = OnDrop::new(move || state.tx_dma_busy.store(false, Ordering::Relaxed));
+
         let ch = self.tx_dma.as_mut().unwrap();
 
         // If we don't assign future to a variable, the data register pointer
         // is held across an await and makes the future non-Send.
-        let transfer = unsafe { ch.write(buffer, r.thr().as_ptr() as *mut u8, Default::default()) };
+        let transfer = unsafe { ch.write(buffer, r.thr().as_ptr() as *mut W, Default::default()) };
         transfer.await;
 
+        drop(on_drop);
+
         #[cfg(ip_feature_uart_fine_fifo_thrld)]
         r.fcrr().modify(|w| {
             w.set_dmae(false);
@@ -346,7 +536,7 @@ impl<'d> UartTx<'d, Async> {
 
     /// Wait until transmission complete
     pub async fn flush(&mut self) -> Result<(), Error> {
-        self.blocking_flush()
+        flush(self.info, self.baudrate()).await
     }
 }
 
@@ -406,6 +596,9 @@ impl<'d, M: Mode> UartTx<'d, M> {
             tx,
             cts,
             de: None,
+            de_alt_num: 0,
+            de_config: config.de,
+            half_duplex: false,
             tx_dma,
             _phantom: PhantomData,
         };
@@ -422,17 +615,120 @@ impl<'d, M: Mode> UartTx<'d, M> {
         //     w.set_ctse(self.cts.is_some());
         //});
         configure(info, self.kernel_clock, config, false, true)?;
+        self.apply_de_config(config);
 
         Ok(())
     }
 
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
-        reconfigure(self.info, self.kernel_clock, config)
+        reconfigure(self.info, self.kernel_clock, config)?;
+        self.apply_de_config(config);
+
+        Ok(())
+    }
+
+    /// Select hardware or software driven DE
+    fn apply_de_config(&mut self, config: &Config) {
+        self.de_config = config.de;
+        self.state.baudrate.store(config.baudrate, Ordering::Relaxed);
+
+        let Some(de) = self.de.as_ref() else {
+            return;
+        };
+        if config.de.is_hardware() {
+            de.set_as_alt(self.de_alt_num);
+        } else {
+            de.set_as_ioc_gpio();
+            self.set_software_de(false);
+            de.set_as_output();
+        }
+    }
+
+    /// DE pin, if it's driven by software
+    fn software_de(&self) -> Option<&PeripheralRef<'d, AnyPin>> {
+        self.de.as_ref().filter(|_| !self.de_config.is_hardware())
+    }
+
+    fn set_software_de(&self, active: bool) {
+        if let Some(de) = self.software_de() {
+            if active == (self.de_config.polarity == DePolarity::ActiveHigh) {
+                de.set_high();
+            } else {
+                de.set_low();
+            }
+        }
+    }
+
+    fn baudrate(&self) -> u32 {
+        self.state.baudrate.load(Ordering::Relaxed)
+    }
+
+    fn bit_periods_us(&self, bits: u8) -> u32 {
+        (bits as u32 * 1_000_000).div_ceil(self.baudrate())
+    }
+
+    /// Whether a write must wait for the transmitter to be empty, i.e. software DE or half-duplex
+    fn waits_tx_end(&self) -> bool {
+        self.half_duplex || self.software_de().is_some()
+    }
+
+    /// Take the line before writing: disable the receiver in half-duplex mode, assert software DE
+    fn begin_tx(&mut self) {
+        if self.half_duplex {
+            set_receiver_enabled(self.info, false);
+        }
+        self.set_software_de(true);
+    }
+
+    /// Release the line after the transmitter is empty
+    fn finish_tx(&mut self) {
+        self.tx_line().release();
+    }
+
+    fn tx_line(&self) -> TxLine<'d> {
+        TxLine {
+            info: self.info,
+            // Safety: only used to deassert DE, `self.de` isn't reconfigured while a write is in progress
+            de: self.software_de().map(|de| unsafe { de.clone_unchecked() }),
+            de_polarity: self.de_config.polarity,
+            half_duplex: self.half_duplex,
+        }
+    }
+
+    /// Take the line, and wait the pre-TX delay of software DE
+    fn blocking_begin_tx(&mut self) {
+        self.begin_tx();
+        if self.software_de().is_some() {
+            blocking_delay_us(self.bit_periods_us(self.de_config.pre_tx_delay));
+        }
+    }
+
+    /// Wait the end of transmission and the post-TX delay of software DE, then release the line
+    fn blocking_end_tx(&mut self) -> Result<(), Error> {
+        if !self.waits_tx_end() {
+            return Ok(());
+        }
+
+        let res = blocking_flush(self.info, self.baudrate());
+        if self.software_de().is_some() {
+            blocking_delay_us(self.bit_periods_us(self.de_config.post_tx_delay));
+        }
+        self.finish_tx();
+
+        res
     }
 
     /// Perform a blocking UART write
     pub fn blocking_write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        self.blocking_begin_tx();
+        let res = self.blocking_write_fifo(buffer);
+        let end_res = self.blocking_end_tx();
+
+        res.and(end_res)
+    }
+
+    fn blocking_write_fifo(&mut self, buffer: &[u8]) -> Result<(), Error> {
         let r = self.info.regs;
 
         for &b in buffer {
@@ -453,9 +749,130 @@ impl<'d, M: Mode> UartTx<'d, M> {
         Ok(())
     }
 
+    /// Perform a blocking UART write of 9-bit words
+    ///
+    /// Bit 8 of each word is the 9th data bit, set it to send an address frame.
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub fn blocking_write_u16(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        self.blocking_begin_tx();
+        let res = self.blocking_write_fifo_u16(buffer);
+        let end_res = self.blocking_end_tx();
+
+        res.and(end_res)
+    }
+
+    #[cfg(ip_feature_uart_9bit_mode)]
+    fn blocking_write_fifo_u16(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        let r = self.info.regs;
+
+        for &w in buffer {
+            let mut retry = 0_u32;
+            while !r.lsr().read().thre() {
+                if retry > HPM_UART_DRV_RETRY_COUNT {
+                    break;
+                }
+                retry += 1;
+            }
+            if retry > HPM_UART_DRV_RETRY_COUNT {
+                return Err(Error::Timeout);
+            }
+
+            // THR is 9 bits wide in 9 bits mode
+            r.thr().write_value(pac::uart::regs::Thr((w & 0x1FF) as _));
+        }
+
+        Ok(())
+    }
+
     /// Block until transmission complete
     pub fn blocking_flush(&mut self) -> Result<(), Error> {
-        blocking_flush(self.info)
+        blocking_flush(self.info, self.baudrate())
+    }
+
+    /// Preload a frame into the TX FIFO, and arm the hardware trigger to send it.
+    ///
+    /// The frame is sent at once when the trigger input of the UART fires, the trigger source
+    /// (e.g. a PWM event) is routed by TRGM. Use [`wait_triggered_sent`](Self::wait_triggered_sent) or
+    /// [`blocking_wait_triggered_sent`](Self::blocking_wait_triggered_sent) to wait for the frame
+    /// to be sent, then call this again to preload the next frame.
+    ///
+    /// The frame must fit in the FIFO, [`Error::BufferTooLong`] otherwise, and the FIFO must be enabled
+    /// in [`Config::fifo_level`], [`Error::FIFO`] otherwise. [`Error::Timeout`] is returned if the
+    /// previous frame is still waiting for its trigger.
+    #[cfg(ip_feature_uart_trig_mode)]
+    pub fn arm_triggered_write(&mut self, frame: &[u8], config: &TriggerConfig) -> Result<(), Error> {
+        let r = self.info.regs;
+
+        // without FIFO, each byte would overwrite THR
+        if !fifo_enabled(r) {
+            return Err(Error::FIFO);
+        }
+        if frame.len() > UART_FIFO_SIZE {
+            return Err(Error::BufferTooLong);
+        }
+
+        // previous frame must be sent
+        self.blocking_flush()?;
+
+        // keep the trigger disarmed while loading the FIFO, so a partial frame is never sent
+        r.moto_cfg().write(|w| {
+            w.set_trg_mode(true);
+            w.set_hwtrg_en(false);
+            w.set_trg_clr_rfifo(config.clear_rx_fifo);
+            if let Some(bits) = config.stop_bit_insert {
+                w.set_txstop_insert(true);
+                w.set_txstp_bits(bits);
+            }
+        });
+
+        for &b in frame {
+            r.thr().write(|w| w.set_thr(b));
+        }
+
+        r.moto_cfg().modify(|w| w.set_hwtrg_en(true));
+
+        Ok(())
+    }
+
+    /// Wait for the frame preloaded by [`arm_triggered_write`](Self::arm_triggered_write) to be sent.
+    ///
+    /// There's no deadline, unlike [`flush`](Self::flush), as the trigger may fire at any time.
+    #[cfg(ip_feature_uart_trig_mode)]
+    pub async fn wait_triggered_sent(&mut self) {
+        let r = self.info.regs;
+
+        while !r.lsr().read().temt() {
+            yield_now().await;
+        }
+    }
+
+    /// Block until the frame preloaded by [`arm_triggered_write`](Self::arm_triggered_write) is sent.
+    ///
+    /// There's no deadline, unlike [`blocking_flush`](Self::blocking_flush), as the trigger may fire at any time.
+    #[cfg(ip_feature_uart_trig_mode)]
+    pub fn blocking_wait_triggered_sent(&mut self) {
+        let r = self.info.regs;
+
+        while !r.lsr().read().temt() {}
+    }
+
+    /// Send the frame preloaded by [`arm_triggered_write`](Self::arm_triggered_write) now, by software.
+    #[cfg(ip_feature_uart_trig_mode)]
+    pub fn software_trigger(&mut self) {
+        let r = self.info.regs;
+
+        r.moto_cfg().modify(|w| {
+            w.set_hwtrg_en(false);
+            w.set_swtrg(true);
+        });
+    }
+
+    /// Leave the triggered transmission mode, further writes are sent immediately.
+    ///
+    /// Data still waiting for a trigger in the TX FIFO is sent too.
+    #[cfg(ip_feature_uart_trig_mode)]
+    pub fn disarm_trigger(&mut self) {
+        self.info.regs.moto_cfg().write(|w| w.0 = 0);
     }
 }
 
@@ -529,17 +946,40 @@ impl<'d> UartRx<'d, Async> {
         self.inner_read(buffer, true).await
     }
 
-    async fn inner_read_run(
+    /// Initiate an asynchronous UART read of 9-bit words
+    ///
+    /// Bit 8 of each word is the 9th data bit, set for address frames.
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub async fn read_u16(&mut self, buffer: &mut [u16]) -> Result<(), Error> {
+        self.inner_read(buffer, false).await?;
+
+        Ok(())
+    }
+
+    /// Initiate an asynchronous read of 9-bit words with idle line detection enabled
+    ///
+    /// With [`Config::address_match`] set, this only completes on frames addressed to this node.
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub async fn read_until_idle_u16(&mut self, buffer: &mut [u16]) -> Result<usize, Error> {
+        self.inner_read(buffer, true).await
+    }
+
+    async fn inner_read_run<W: Word>(
         &mut self,
-        buffer: &mut [u8],
+        buffer: &mut [W],
         enable_idle_line_detection: bool,
     ) -> Result<ReadCompletionEvent, Error> {
         let r = self.info.regs;
 
         let _ = r.lsr().read(); // clear error flags
 
+        let state = self.state;
+        state.rx_dma_busy.store(true, Ordering::Relaxed);
+
         // make sure UART state is restored to neutral state when this future is dropped
         let on_drop = OnDrop::new(move || {
+            state.rx_dma_busy.store(false, Ordering::Relaxed);
+
             r.ier().modify(|w| {
                 w.set_elsi(false); // rx status
                 w.set_ethei(false); // tx status
@@ -567,7 +1007,7 @@ impl<'d> UartRx<'d, Async> {
 
         let buffer_len = buffer.len();
 
-        let transfer = unsafe { ch.read(r.rbr().as_ptr() as *mut u8, buffer, Default::default()) };
+        let transfer = unsafe { ch.read(r.rbr().as_ptr() as *mut W, buffer, Default::default()) };
 
         if !self.detect_previous_overrun {
             // clear overrun flag
@@ -658,7 +1098,11 @@ impl<'d> UartRx<'d, Async> {
         r
     }
 
-    async fn inner_read(&mut self, buffer: &mut [u8], enable_idle_line_detection: bool) -> Result<usize, Error> {
+    async fn inner_read<W: Word>(
+        &mut self,
+        buffer: &mut [W],
+        enable_idle_line_detection: bool,
+    ) -> Result<usize, Error> {
         if buffer.is_empty() {
             return Ok(0);
         } else if buffer.len() > 0xFFFF {
@@ -752,7 +1196,10 @@ impl<'d, M: Mode> UartRx<'d, M> {
 
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
-        reconfigure(self.info, self.kernel_clock, config)
+        reconfigure(self.info, self.kernel_clock, config)?;
+        self.state.baudrate.store(config.baudrate, Ordering::Relaxed);
+
+        Ok(())
     }
 
     fn check_rx_flags(&mut self) -> Result<bool, Error> {
@@ -793,6 +1240,21 @@ impl<'d, M: Mode> UartRx<'d, M> {
         }
         Ok(())
     }
+
+    /// Perform a blocking read of 9-bit words into `buffer`
+    ///
+    /// Bit 8 of each word is the 9th data bit, set for address frames.
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub fn blocking_read_u16(&mut self, buffer: &mut [u16]) -> Result<(), Error> {
+        let r = self.info.regs;
+
+        for w in buffer {
+            while !self.check_rx_flags()? {}
+            // RBR is 9 bits wide in 9 bits mode
+            *w = (r.rbr().read().0 & 0x1FF) as u16;
+        }
+        Ok(())
+    }
 }
 
 /// Bidirectional UART Driver, which acts as a combination of [`UartTx`] and [`UartRx`].
@@ -832,6 +1294,59 @@ impl<'d> Uart<'d, Async> {
         )
     }
 
+    /// Create a new single-wire half-duplex UART
+    ///
+    /// The UART has no internal TX to RX connection, so the TX and RX pins are both wired to the bus line.
+    /// TX is open-drain with the internal pull-up, the receiver is disabled while transmitting and
+    /// enabled again when the transmitter is empty. On IP cores without a receiver enable,
+    /// the echo of the transmission is dropped instead.
+    pub fn new_half_duplex<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(rx, tx);
+
+        T::add_resource_group(0);
+
+        rx.set_as_alt(rx.alt_num());
+        set_as_half_duplex_tx(&tx);
+
+        let mut this = Self::new_inner(
+            peri,
+            Some(rx.map_into()),
+            Some(tx.map_into()),
+            None,
+            None,
+            None,
+            new_dma!(tx_dma),
+            new_dma!(rx_dma),
+            config,
+        )?;
+        this.tx.half_duplex = true;
+
+        Ok(this)
+    }
+
+    /// Send a request, then receive the response until the line is idle.
+    ///
+    /// Mostly useful in half-duplex mode, the receiver is enabled right after the request is sent.
+    /// Returns the length of the response.
+    pub async fn request(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize, Error> {
+        self.write(request).await?;
+        self.read_until_idle(response).await
+    }
+
+    /// Send a request, then receive a response of exactly `response.len()` bytes.
+    pub async fn request_exact(&mut self, request: &[u8], response: &mut [u8]) -> Result<(), Error> {
+        self.write(request).await?;
+        self.read(response).await
+    }
+
     /// Create a new bidirectional UART with request-to-send and clear-to-send pins
     pub fn new_with_rtscts<T: Instance>(
         peri: impl Peripheral<P = T> + 'd,
@@ -912,9 +1427,59 @@ impl<'d> Uart<'d, Async> {
     pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
         self.rx.read_until_idle(buffer).await
     }
+
+    /// Perform an asynchronous write of 9-bit words
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub async fn write_u16(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        self.tx.write_u16(buffer).await
+    }
+
+    /// Perform an asynchronous read of 9-bit words into `buffer`
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub async fn read_u16(&mut self, buffer: &mut [u16]) -> Result<(), Error> {
+        self.rx.read_u16(buffer).await
+    }
+
+    /// Perform an asynchronous read of 9-bit words with idle line detection enabled
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub async fn read_until_idle_u16(&mut self, buffer: &mut [u16]) -> Result<usize, Error> {
+        self.rx.read_until_idle_u16(buffer).await
+    }
 }
 
 impl<'d> Uart<'d, Blocking> {
+    /// Create a new blocking single-wire half-duplex UART
+    ///
+    /// See [`Uart::new_half_duplex`] for the wiring.
+    pub fn new_blocking_half_duplex<T: Instance>(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(rx, tx);
+
+        T::add_resource_group(0);
+
+        rx.set_as_alt(rx.alt_num());
+        set_as_half_duplex_tx(&tx);
+
+        let mut this = Self::new_inner(
+            peri,
+            Some(rx.map_into()),
+            Some(tx.map_into()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config,
+        )?;
+        this.tx.half_duplex = true;
+
+        Ok(this)
+    }
+
     /// Create a new blocking bidirectional UART.
     pub fn new_blocking<T: Instance>(
         peri: impl Peripheral<P = T> + 'd,
@@ -1024,6 +1589,9 @@ impl<'d, M: Mode> Uart<'d, M> {
         }
         let kernel_clock = T::frequency();
 
+        // DE pin is already in its alt function, keep it to switch back from software DE
+        let de_alt_num = de.as_ref().map_or(0, |de| de.ioc_pad().func_ctl().read().alt_select());
+
         let mut this = Self {
             tx: UartTx {
                 _phantom: PhantomData,
@@ -1033,6 +1601,9 @@ impl<'d, M: Mode> Uart<'d, M> {
                 tx,
                 cts,
                 de,
+                de_alt_num,
+                de_config: config.de,
+                half_duplex: false,
                 tx_dma,
             },
             rx: UartRx {
@@ -1056,6 +1627,7 @@ impl<'d, M: Mode> Uart<'d, M> {
         state.tx_rx_refcount.store(2, Ordering::Relaxed);
 
         configure(info, self.rx.kernel_clock, config, true, true)?;
+        self.tx.apply_de_config(config);
 
         info.interrupt.unpend();
         unsafe { info.interrupt.enable() };
@@ -1063,6 +1635,11 @@ impl<'d, M: Mode> Uart<'d, M> {
         Ok(())
     }
 
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.tx.set_config(config)
+    }
+
     /// Perform a blocking write
     pub fn blocking_write(&mut self, buffer: &[u8]) -> Result<(), Error> {
         self.tx.blocking_write(buffer)
@@ -1083,28 +1660,193 @@ impl<'d, M: Mode> Uart<'d, M> {
         self.rx.blocking_read(buffer)
     }
 
+    /// Perform a blocking write of 9-bit words
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub fn blocking_write_u16(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        self.tx.blocking_write_u16(buffer)
+    }
+
+    /// Perform a blocking read of 9-bit words into `buffer`
+    #[cfg(ip_feature_uart_9bit_mode)]
+    pub fn blocking_read_u16(&mut self, buffer: &mut [u16]) -> Result<(), Error> {
+        self.rx.blocking_read_u16(buffer)
+    }
+
     /// Split the Uart into a transmitter and receiver, which is
     /// particularly useful when having two tasks correlating to
     /// transmitting and receiving.
     pub fn split(self) -> (UartTx<'d, M>, UartRx<'d, M>) {
         (self.tx, self.rx)
     }
+
+    /// Split the Uart into a transmitter and receiver, plus a handle to change
+    /// the shared baud rate and framing of both halves at runtime.
+    pub fn split_with_config_handle(self) -> (UartTx<'d, M>, UartRx<'d, M>, UartConfigHandle<'d>) {
+        let info = self.rx.info;
+        let state = self.rx.state;
+
+        critical_section::with(|_| {
+            let refcount = state.tx_rx_refcount.load(Ordering::Relaxed);
+            state.tx_rx_refcount.store(refcount + 1, Ordering::Relaxed);
+        });
+
+        let handle = UartConfigHandle {
+            info,
+            state,
+            kernel_clock: self.rx.kernel_clock,
+            de_config: self.tx.de_config,
+            _phantom: PhantomData,
+        };
+
+        (self.tx, self.rx, handle)
+    }
+}
+
+/// Handle to change the baud rate and framing of a split UART at runtime.
+///
+/// The divisor and LCR are shared by [`UartTx`] and [`UartRx`], so changing them from one half
+/// while the other half is busy corrupts the transfer. This handle drains TX first, and refuses to
+/// reconfigure while a DMA transfer is in flight on either half.
+///
+/// Can be obtained from [`Uart::split_with_config_handle`].
+pub struct UartConfigHandle<'d> {
+    info: &'static Info,
+    state: &'static State,
+    kernel_clock: Hertz,
+    de_config: DeConfig,
+    _phantom: PhantomData<&'d ()>,
+}
+
+impl<'d> UartConfigHandle<'d> {
+    /// Change baud rate, parity, stop bits and data bits of both halves.
+    ///
+    /// Blocks until pending TX data is sent. Returns [`ConfigError::DmaInFlight`] if an async read or
+    /// write is running on either half, in this case nothing is changed.
+    ///
+    /// DE settings belong to the TX half, change them with [`UartTx::set_config`]. `config.de` must be
+    /// the DE settings of the UART when it was split, [`ConfigError::DeChanged`] otherwise.
+    pub fn set_config(&self, config: &Config) -> Result<(), ConfigError> {
+        if config.de != self.de_config {
+            return Err(ConfigError::DeChanged);
+        }
+        if self.dma_in_flight() {
+            return Err(ConfigError::DmaInFlight);
+        }
+
+        blocking_flush(self.info, self.state.baudrate.load(Ordering::Relaxed))
+            .map_err(|_| ConfigError::TxNotDrained)?;
+
+        // no other task can start a transfer or write to the FIFO in between
+        critical_section::with(|_| {
+            if self.dma_in_flight() {
+                return Err(ConfigError::DmaInFlight);
+            }
+            if !self.info.regs.lsr().read().temt() {
+                return Err(ConfigError::TxNotDrained);
+            }
+
+            reconfigure(self.info, self.kernel_clock, config)?;
+            self.state.baudrate.store(config.baudrate, Ordering::Relaxed);
+
+            Ok(())
+        })
+    }
+
+    /// Whether an async read or write is running on either half
+    pub fn dma_in_flight(&self) -> bool {
+        self.state.tx_dma_busy.load(Ordering::Relaxed) || self.state.rx_dma_busy.load(Ordering::Relaxed)
+    }
+}
+
+impl<'d> Drop for UartConfigHandle<'d> {
+    fn drop(&mut self) {
+        drop_tx_rx(self.info, self.state);
+    }
 }
 
 // ==========
 // internal functions
-fn blocking_flush(info: &Info) -> Result<(), Error> {
+
+/// TX pin in open-drain with pull-up, as a shared single-wire bus line
+fn set_as_half_duplex_tx<T: Instance>(tx: &PeripheralRef<'_, impl TxPin<T>>) {
+    tx.set_as_alt(tx.alt_num());
+    tx.ioc_pad().pad_ctl().modify(|w| {
+        w.set_od(true);
+        w.set_pe(true);
+        w.set_ps(true); // pull up
+    });
+}
+
+fn blocking_delay_us(us: u32) {
+    if us > 0 {
+        McycleDelay::new(crate::sysctl::clocks().cpu0.0).delay_us(us);
+    }
+}
+
+async fn delay_us(us: u32) {
+    #[cfg(feature = "time")]
+    if us > 0 {
+        embassy_time::Timer::after_micros(us as u64).await;
+    }
+
+    #[cfg(not(feature = "time"))]
+    blocking_delay_us(us);
+}
+
+fn set_receiver_enabled(info: &Info, enable: bool) {
+    #[cfg(ip_feature_uart_rx_en)]
+    info.regs.idle_cfg().modify(|w| w.set_rxen(enable));
+
+    #[cfg(not(ip_feature_uart_rx_en))]
+    let _ = (info, enable);
+}
+
+/// Whether the FIFO is enabled, see [`Config::fifo_level`]
+fn fifo_enabled(r: pac::uart::Uart) -> bool {
+    #[cfg(ip_feature_uart_fine_fifo_thrld)]
+    return r.fcrr().read().fifoe();
+
+    // FCR is write-only, its value is stored in GPR
+    #[cfg(not(ip_feature_uart_fine_fifo_thrld))]
+    return pac::uart::regs::Fcr(r.gpr().read().data() as _).fifoe();
+}
+
+/// Max time for the transmitter to be empty, with a full FIFO, in CPU cycles
+fn tx_drain_cycles(baudrate: u32) -> u64 {
+    // up to 13 bit times per frame: start, 9 data, parity and 2 stop bits, for the FIFO and the shift register
+    let bits = (UART_FIFO_SIZE as u64 + 1) * 13;
+    let cpu_freq = crate::sysctl::clocks().cpu0.0 as u64;
+
+    // twice the nominal time, as a margin
+    2 * bits * cpu_freq / baudrate.max(HPM_UART_MINIMUM_BAUDRATE) as u64
+}
+
+/// Wait for the transmitter to be empty (LSR.TEMT), i.e. the stop bit of the last frame is sent
+fn blocking_flush(info: &Info, baudrate: u32) -> Result<(), Error> {
     let r = info.regs;
-    let mut retry = 0_u32;
+    let start = mcycle::read64();
+    let timeout = tx_drain_cycles(baudrate);
 
     while !r.lsr().read().temt() {
-        if retry > HPM_UART_DRV_RETRY_COUNT {
-            break;
+        if mcycle::read64() - start > timeout {
+            return Err(Error::Timeout);
         }
-        retry += 1;
     }
-    if retry > HPM_UART_DRV_RETRY_COUNT {
-        return Err(Error::Timeout);
+
+    Ok(())
+}
+
+/// Wait for the transmitter to be empty (LSR.TEMT), yielding to other tasks
+async fn flush(info: &Info, baudrate: u32) -> Result<(), Error> {
+    let r = info.regs;
+    let start = mcycle::read64();
+    let timeout = tx_drain_cycles(baudrate);
+
+    while !r.lsr().read().temt() {
+        if mcycle::read64() - start > timeout {
+            return Err(Error::Timeout);
+        }
+        yield_now().await;
     }
 
     Ok(())
@@ -1113,7 +1855,11 @@ fn blocking_flush(info: &Info) -> Result<(), Error> {
 fn reconfigure(info: &Info, kernel_clock: Hertz, config: &Config) -> Result<(), ConfigError> {
     info.interrupt.disable();
 
-    configure(info, kernel_clock, config, true, true)?;
+    // keep the interrupts enabled by reads and writes in progress, configure clears them
+    let ier = info.regs.ier().read();
+    let res = configure(info, kernel_clock, config, true, true);
+    info.regs.ier().write_value(ier);
+    res?;
 
     info.interrupt.unpend();
     unsafe { info.interrupt.enable() };
@@ -1133,6 +1879,10 @@ fn configure(
     if !enable_rx && !enable_tx {
         return Err(ConfigError::RxOrTxNotEnabled);
     }
+    #[cfg(ip_feature_uart_addr_match)]
+    if config.address_match.is_some() && config.data_bits != DataBits::DataBits9 {
+        return Err(ConfigError::AddressMatchRequires9Bits);
+    }
 
     // disable all interrupts
     r.ier().write(|w| w.0 = 0);
@@ -1171,9 +1921,34 @@ fn configure(
             }
         }
         w.set_stb(config.stop_bits != StopBits::STOP1); // STOP1: 0
-        w.set_wls(config.data_bits as _); // 8 bits
+        match config.data_bits {
+            // 9 bits mode uses 8 bits word length, the 9th bit is enabled in ADDR_CFG
+            #[cfg(ip_feature_uart_9bit_mode)]
+            DataBits::DataBits9 => w.set_wls(DataBits::DataBits8 as _),
+            data_bits => w.set_wls(data_bits as _),
+        }
     });
 
+    #[cfg(ip_feature_uart_9bit_mode)]
+    {
+        let nine_bits = config.data_bits == DataBits::DataBits9;
+        r.addr_cfg().write(|w| {
+            w.set_txen_9bit(nine_bits);
+            w.set_rxen_9bit(nine_bits);
+
+            #[cfg(ip_feature_uart_addr_match)]
+            if let Some(address_match) = config.address_match {
+                w.set_rxen_addr_msb(true);
+                w.set_a0_en(true);
+                w.set_addr0(address_match.addr0);
+                if let Some(addr1) = address_match.addr1 {
+                    w.set_a1_en(true);
+                    w.set_addr1(addr1);
+                }
+            }
+        });
+    }
+
     // FIFO setting
     #[cfg(not(ip_feature_uart_fine_fifo_thrld))]
     {
@@ -1236,7 +2011,6 @@ fn calculate_baudrate(freq: u32, baudrate: u32) -> Option<(u16, u8)> {
     const HPM_UART_OSC_MIN: u8 = 8;
     const HPM_UART_BAUDRATE_DIV_MAX: u16 = 0xFFFF;
     const HPM_UART_BAUDRATE_DIV_MIN: u16 = 1;
-    const HPM_UART_MINIMUM_BAUDRATE: u32 = 200;
 
     const UART_SOC_OVERSAMPLE_MAX: u8 = HPM_UART_OSC_MAX;
 
@@ -1319,6 +2093,7 @@ impl embedded_hal_nb::serial::Error for Error {
             Self::Timeout => embedded_hal_nb::serial::ErrorKind::Other,
             Self::FIFO => embedded_hal_nb::serial::ErrorKind::Overrun,
             Self::LineBreak => embedded_hal_nb::serial::ErrorKind::Other,
+            Self::AutoBaud => embedded_hal_nb::serial::ErrorKind::Other,
         }
     }
 }
@@ -1369,7 +2144,11 @@ impl<'d, M: Mode> embedded_hal_nb::serial::Write for Uart<'d, M> {
 
 impl embedded_io::Error for Error {
     fn kind(&self) -> embedded_io::ErrorKind {
-        embedded_io::ErrorKind::Other
+        match *self {
+            Self::BufferTooLong => embedded_io::ErrorKind::InvalidInput,
+            Self::Timeout => embedded_io::ErrorKind::TimedOut,
+            _ => embedded_io::ErrorKind::Other,
+        }
     }
 }
 
@@ -1387,6 +2166,10 @@ impl<M: Mode> embedded_io::Write for UartTx<'_, M> {
     }
 }
 
+impl<M: Mode> embedded_io::ErrorType for UartRx<'_, M> {
+    type Error = Error;
+}
+
 impl<M: Mode> embedded_io::ErrorType for Uart<'_, M> {
     type Error = Error;
 }
@@ -1423,6 +2206,33 @@ impl embedded_io_async::Write for UartTx<'_, Async> {
     }
 }
 
+/// Reads until the buffer is full or an idle line is detected, whichever comes first.
+///
+/// At least one byte is returned for a non-empty buffer. Buffers longer than one DMA transfer
+/// are read partially.
+impl embedded_io_async::Read for UartRx<'_, Async> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(0xFFFF);
+        loop {
+            let n = self.read_until_idle(&mut buf[..len]).await?;
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// See [`UartRx`]'s implementation.
+impl embedded_io_async::Read for Uart<'_, Async> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io_async::Read::read(&mut self.rx, buf).await
+    }
+}
+
 // ==========
 // helper types and functions
 
@@ -1430,6 +2240,9 @@ struct State {
     rx_waker: AtomicWaker,
     tx_rx_refcount: AtomicU8,
     saved_lsr: AtomicU32,
+    baudrate: AtomicU32,
+    tx_dma_busy: AtomicBool,
+    rx_dma_busy: AtomicBool,
 }
 impl State {
     const fn new() -> Self {
@@ -1437,6 +2250,9 @@ impl State {
             rx_waker: AtomicWaker::new(),
             tx_rx_refcount: AtomicU8::new(0),
             saved_lsr: AtomicU32::new(0),
+            baudrate: AtomicU32::new(115200),
+            tx_dma_busy: AtomicBool::new(false),
+            rx_dma_busy: AtomicBool::new(false),
         }
     }
 }
@@ -1451,6 +2267,7 @@ struct Info {
 pub(crate) trait SealedInstance: crate::sysctl::ClockPeripheral {
     fn info() -> &'static Info;
     fn state() -> &'static State;
+    fn buffered_state() -> &'static buffered::State;
 }
 
 /// USART peripheral instance trait.
@@ -1486,6 +2303,11 @@ macro_rules! impl_uart {
                 static STATE: State = State::new();
                 &STATE
             }
+
+            fn buffered_state() -> &'static buffered::State {
+                static STATE: buffered::State = buffered::State::new();
+                &STATE
+            }
         }
 
         impl Instance for crate::peripherals::$inst {
@@ -0,0 +1,282 @@
+//! Ring-buffered UART receiver, using DMA circular mode.
+//!
+//! The DMA keeps writing received bytes into a user provided buffer in the background,
+//! so no byte is lost between two `read` calls, as long as the buffer is read fast enough.
+
+use core::future::poll_fn;
+use core::mem;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::PeripheralRef;
+use futures_util::future::{select, Either};
+
+use super::{drop_tx_rx, reconfigure, Config, ConfigError, Error, Info, State, UartRx};
+use crate::dma::ReadableRingBuffer;
+use crate::gpio::{AnyPin, SealedPin};
+use crate::mode::Async;
+use crate::pac;
+use crate::time::Hertz;
+
+/// Rx-only Ring-buffered UART Driver
+///
+/// Created with [UartRx::into_ring_buffered]
+pub struct RingBufferedUartRx<'d> {
+    info: &'static Info,
+    state: &'static State,
+    kernel_clock: Hertz,
+    rx: Option<PeripheralRef<'d, AnyPin>>,
+    rts: Option<PeripheralRef<'d, AnyPin>>,
+    ring_buf: ReadableRingBuffer<'d, u8>,
+}
+
+impl<'d> UartRx<'d, Async> {
+    /// Turn the `UartRx` into a buffered uart which can continuously receive in the background
+    /// without the possibility of losing bytes. The `dma_buf` is a buffer registered to the
+    /// DMA controller, and must be large enough to prevent overruns.
+    pub fn into_ring_buffered(mut self, dma_buf: &'d mut [u8]) -> RingBufferedUartRx<'d> {
+        assert!(!dma_buf.is_empty() && dma_buf.len() <= 0xFFFF);
+
+        let info = self.info;
+        let state = self.state;
+        let kernel_clock = self.kernel_clock;
+
+        let rx_dma = self.rx_dma.take().unwrap();
+        let ring_buf = unsafe {
+            ReadableRingBuffer::new(
+                rx_dma.channel,
+                rx_dma.request,
+                info.regs.rbr().as_ptr() as *mut u8,
+                dma_buf,
+                Default::default(),
+            )
+        };
+
+        let rx = self.rx.take();
+        let rts = self.rts.take();
+
+        // Don't disable the clock, the refcount is taken over by `RingBufferedUartRx`
+        mem::forget(self);
+
+        RingBufferedUartRx {
+            info,
+            state,
+            kernel_clock,
+            rx,
+            rts,
+            ring_buf,
+        }
+    }
+}
+
+impl<'d> RingBufferedUartRx<'d> {
+    /// Reconfigure the driver.
+    ///
+    /// The ring buffer is stopped, unread data is dropped. The next `read` restarts it.
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.teardown_uart();
+        reconfigure(self.info, self.kernel_clock, config)
+    }
+
+    /// Configure and start the DMA backed UART receiver
+    ///
+    /// Note: This is also done automatically by [`read`](Self::read) if required.
+    pub fn start(&mut self) -> Result<(), Error> {
+        self.setup_uart();
+        Ok(())
+    }
+
+    /// Stop the DMA backed UART receiver, returns the error
+    fn stop(&mut self, err: Error) -> Result<usize, Error> {
+        self.teardown_uart();
+
+        Err(err)
+    }
+
+    /// Read bytes that are readily available in the ring buffer.
+    /// If no bytes are currently available in the buffer the call waits until some
+    /// bytes are available (at least one byte and at most half the buffer size),
+    /// or an idle line is detected.
+    ///
+    /// Background receive is started if `start` has not been previously called.
+    ///
+    /// Receive in the background is terminated if an error is returned.
+    /// It must then manually be started again by calling `start` or by re-calling `read`.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        // Start DMA and UART if it was not already started,
+        // otherwise check for errors saved by the interrupt handler.
+        if !self.ring_buf.is_running() {
+            self.start()?;
+        } else if let Err(err) = check_saved_status(self.info.regs, self.state) {
+            return self.stop(err);
+        }
+
+        loop {
+            match self.ring_buf.read(buf) {
+                Ok((0, _)) => {}
+                Ok((len, _)) => return Ok(len),
+                Err(_) => return self.stop(Error::Overrun),
+            }
+
+            match self.wait_for_data_or_idle().await {
+                Ok(_) => {}
+                Err(err) => return self.stop(err),
+            }
+        }
+    }
+
+    fn setup_uart(&mut self) {
+        let r = self.info.regs;
+
+        // clear error flags
+        let _ = r.lsr().read();
+        self.state.saved_lsr.store(0, Ordering::Relaxed);
+
+        self.state.rx_dma_busy.store(true, Ordering::Relaxed);
+        self.ring_buf.start();
+
+        // recv status change
+        r.ier().modify(|w| w.set_elsi(true));
+
+        set_dma_enable(r, true);
+
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    fn teardown_uart(&mut self) {
+        let r = self.info.regs;
+
+        r.ier().modify(|w| {
+            w.set_elsi(false); // rx status
+            #[cfg(ip_feature_uart_rx_idle_detect)]
+            w.set_erxidle(false);
+        });
+
+        #[cfg(ip_feature_uart_rx_idle_detect)]
+        r.idle_cfg().modify(|w| w.set_rx_idle_en(false));
+
+        set_dma_enable(r, false);
+
+        compiler_fence(Ordering::SeqCst);
+
+        self.ring_buf.request_stop();
+        while self.ring_buf.is_running() {}
+
+        self.state.rx_dma_busy.store(false, Ordering::Relaxed);
+    }
+
+    /// Wait for the DMA to reach half or full of the buffer, or an idle line, or an error.
+    async fn wait_for_data_or_idle(&mut self) -> Result<(), Error> {
+        let r = self.info.regs;
+        let s = self.state;
+
+        // idle line detection is disabled by the interrupt handler once triggered
+        #[cfg(ip_feature_uart_rx_idle_detect)]
+        {
+            r.ier().modify(|w| w.set_erxidle(true));
+            r.idle_cfg().modify(|w| w.set_rx_idle_en(true));
+        }
+
+        compiler_fence(Ordering::SeqCst);
+
+        // Future which completes when the DMA buffer is half full or full
+        let mut dma_init = false;
+        let ring_buf = &mut self.ring_buf;
+        let dma = poll_fn(|cx| {
+            ring_buf.set_waker(cx.waker());
+
+            let status = match dma_init {
+                false => Poll::Pending,
+                true => Poll::Ready(()),
+            };
+
+            dma_init = true;
+            status
+        });
+
+        // Future which completes when an idle line or an error is detected
+        let uart = poll_fn(|cx| {
+            s.rx_waker.register(cx.waker());
+
+            compiler_fence(Ordering::SeqCst);
+
+            match check_saved_status(r, s) {
+                Ok(true) => Poll::Ready(Ok(())),
+                Ok(false) => Poll::Pending,
+                Err(err) => Poll::Ready(Err(err)),
+            }
+        });
+
+        match select(dma, uart).await {
+            Either::Left(((), _)) => Ok(()),
+            Either::Right((result, _)) => result,
+        }
+    }
+}
+
+impl Drop for RingBufferedUartRx<'_> {
+    fn drop(&mut self) {
+        self.teardown_uart();
+
+        self.rx.as_ref().map(|x| x.set_as_default());
+        self.rts.as_ref().map(|x| x.set_as_default());
+        drop_tx_rx(self.info, self.state);
+    }
+}
+
+/// Check the line status saved by the interrupt handler, and clear it.
+///
+/// Returns whether an idle line is detected.
+fn check_saved_status(r: pac::uart::Uart, s: &State) -> Result<bool, Error> {
+    // R1C
+    let lsr = pac::uart::regs::Lsr(s.saved_lsr.load(Ordering::Relaxed));
+    s.saved_lsr.store(0, Ordering::Relaxed);
+
+    if lsr.pe() {
+        return Err(Error::Parity);
+    } else if lsr.fe() {
+        return Err(Error::Framing);
+    } else if lsr.oe() {
+        return Err(Error::Overrun);
+    } else if lsr.errf() {
+        return Err(Error::FIFO);
+    } else if lsr.lbreak() {
+        return Err(Error::LineBreak);
+    }
+
+    #[cfg(all(ip_feature_uart_rx_idle_detect, ip_feature_uart_9bit_mode))]
+    if lsr.rxidle() {
+        return Ok(true);
+    }
+    #[cfg(ip_feature_uart_e00018_fix)]
+    if r.iir2().read().rxidle_flag() {
+        return Ok(true);
+    }
+    let _ = r;
+
+    Ok(false)
+}
+
+fn set_dma_enable(r: pac::uart::Uart, enable: bool) {
+    #[cfg(ip_feature_uart_fine_fifo_thrld)]
+    r.fcrr().modify(|w| w.set_dmae(enable));
+    #[cfg(not(ip_feature_uart_fine_fifo_thrld))]
+    {
+        let mut fcr = pac::uart::regs::Fcr(r.gpr().read().data() as _);
+        fcr.set_dmae(enable);
+        r.fcr().write_value(fcr);
+    }
+}
+
+// ==========
+// eh traits
+
+impl embedded_io::ErrorType for RingBufferedUartRx<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for RingBufferedUartRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read(buf).await
+    }
+}